@@ -0,0 +1,156 @@
+use std::collections::{BTreeSet, HashSet};
+use std::hash::Hash;
+
+use aion_types::{Address, H128};
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use accounts::FVMKey;
+
+/// Kind of a recorded storage access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    /// Value was read.
+    Read,
+    /// Value was written.
+    Write,
+}
+
+/// Single storage access of an account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageAccess {
+    /// Read or write.
+    pub kind: AccessKind,
+    /// Storage key.
+    pub key: Bytes,
+    /// Value read or written.
+    pub value: Bytes,
+}
+
+/// Records the storage accesses of accounts it is attached to, in order.
+/// Attach one with `AVMAccount::set_access_recorder`; clones of the account share it.
+#[derive(Debug, Default)]
+pub struct AccessRecorder {
+    accesses: Mutex<Vec<StorageAccess>>,
+}
+
+impl AccessRecorder {
+    /// Create an empty recorder.
+    pub fn new() -> Self { AccessRecorder::default() }
+
+    /// Record an access.
+    pub fn record(&self, kind: AccessKind, key: &[u8], value: &[u8]) {
+        self.accesses.lock().push(StorageAccess {
+            kind: kind,
+            key: key.to_vec(),
+            value: value.to_vec(),
+        });
+    }
+
+    /// Accesses recorded so far.
+    pub fn accesses(&self) -> Vec<StorageAccess> { self.accesses.lock().clone() }
+
+    /// Take the recorded accesses, leaving the recorder empty.
+    pub fn drain(&self) -> Vec<StorageAccess> { ::std::mem::take(&mut *self.accesses.lock()) }
+
+    /// Distinct keys that were read or written, ordered.
+    pub fn access_list(&self) -> BTreeSet<Bytes> {
+        self.accesses
+            .lock()
+            .iter()
+            .map(|access| access.key.clone())
+            .collect()
+    }
+}
+
+/// Storage key of either VM, as tracked in a `ReadWriteSet`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum StorageKey {
+    /// Key of a FastVM account. Normal and wide values share the slot of their key.
+    FVM(H128),
+    /// Key of an AVM account.
+    AVM(Bytes),
+}
+
+impl From<FVMKey> for StorageKey {
+    fn from(key: FVMKey) -> Self {
+        match key {
+            FVMKey::Normal(key) | FVMKey::Wide(key) => StorageKey::FVM(key),
+        }
+    }
+}
+
+impl From<Bytes> for StorageKey {
+    fn from(key: Bytes) -> Self { StorageKey::AVM(key) }
+}
+
+/// Accounts and storage slots read or written, e.g. by one transaction.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReadWriteSet {
+    /// Accounts that were loaded.
+    pub reads: HashSet<Address>,
+    /// Accounts whose balance, nonce or code changed.
+    pub writes: HashSet<Address>,
+    /// Storage slots that were read.
+    pub storage_reads: HashSet<(Address, StorageKey)>,
+    /// Storage slots that were written.
+    pub storage_writes: HashSet<(Address, StorageKey)>,
+}
+
+impl ReadWriteSet {
+    /// Whether executing against `other` in parallel could change the outcome:
+    /// one side writes an account or slot the other side reads or writes.
+    pub fn conflicts_with(&self, other: &ReadWriteSet) -> bool {
+        fn overlaps<T: Eq + Hash>(writes: &HashSet<T>, reads: &HashSet<T>, other_writes: &HashSet<T>) -> bool {
+            writes.iter().any(|item| reads.contains(item) || other_writes.contains(item))
+        }
+        overlaps(&self.writes, &other.reads, &other.writes)
+            || overlaps(&other.writes, &self.reads, &self.writes)
+            || overlaps(&self.storage_writes, &other.storage_reads, &other.storage_writes)
+            || overlaps(&other.storage_writes, &self.storage_reads, &self.storage_writes)
+    }
+
+    /// Add all accesses of `other`.
+    pub fn extend(&mut self, other: ReadWriteSet) {
+        self.reads.extend(other.reads);
+        self.writes.extend(other.writes);
+        self.storage_reads.extend(other.storage_reads);
+        self.storage_writes.extend(other.storage_writes);
+    }
+}
+
+/// Collects a `ReadWriteSet` from all accounts it is attached to with
+/// `CommonAccount::track_access`.
+#[derive(Debug, Default)]
+pub struct ReadWriteTracker {
+    set: Mutex<ReadWriteSet>,
+}
+
+impl ReadWriteTracker {
+    /// Create an empty tracker.
+    pub fn new() -> Self { ReadWriteTracker::default() }
+
+    /// Record an access to an account.
+    pub fn record(&self, kind: AccessKind, address: &Address) {
+        let mut set = self.set.lock();
+        match kind {
+            AccessKind::Read => set.reads.insert(*address),
+            AccessKind::Write => set.writes.insert(*address),
+        };
+    }
+
+    /// Record an access to a storage slot.
+    pub fn record_storage(&self, kind: AccessKind, address: &Address, key: StorageKey) {
+        let mut set = self.set.lock();
+        match kind {
+            AccessKind::Read => set.storage_reads.insert((*address, key)),
+            AccessKind::Write => set.storage_writes.insert((*address, key)),
+        };
+    }
+
+    /// Accesses recorded so far.
+    pub fn set(&self) -> ReadWriteSet { self.set.lock().clone() }
+
+    /// Take the recorded accesses, leaving the tracker empty.
+    pub fn take(&self) -> ReadWriteSet { ::std::mem::take(&mut *self.set.lock()) }
+}
@@ -22,18 +22,24 @@
 
 //! DB backend wrapper for Account trie
 use std::collections::HashMap;
-use blake2b::{BLAKE2B_NULL_RLP, blake2b};
+use blake2b::{BLAKE2B_EMPTY, BLAKE2B_NULL_RLP, blake2b};
 use aion_types::H256;
 use kvdb::{DBValue,HashStore};
-use rlp::NULL_RLP;
+use rlp::{NULL_RLP, Rlp};
+use trie::{Trie, TrieDB, TrieError};
+use trie::node::Node;
+use error::{Error, Result};
 
 #[cfg(test)]
 use aion_types::Address;
 
+/// Maps a node hash of an account to the key it is stored under.
+type Combine = fn(&H256, &H256) -> H256;
+
 // combines a key with an address hash to ensure uniqueness.
 // leaves the first 96 bits untouched in order to support partial key lookup.
 #[inline]
-fn combine_key<'a>(address_hash: &'a H256, key: &'a H256) -> H256 {
+fn combine_key(address_hash: &H256, key: &H256) -> H256 {
     let mut dst = key.clone();
     {
         let last_src: &[u8] = &*address_hash;
@@ -46,13 +52,92 @@ fn combine_key<'a>(address_hash: &'a H256, key: &'a H256) -> H256 {
     dst
 }
 
+// hashes a key together with an address hash.
+// unlike `combine_key` no two (address, key) pairs can be made to collide.
+#[inline]
+fn hash_key(address_hash: &H256, key: &H256) -> H256 {
+    let mut buf = [0u8; 64];
+    buf[..32].copy_from_slice(address_hash);
+    buf[32..].copy_from_slice(key);
+    blake2b(&buf[..])
+}
+
+// keys of the account stored under `combine`, recognised by the stored value
+// hashing to the unmangled key. entries that are pending removal are skipped.
+// this scans and hashes the whole backing store on every call and sees only what its
+// `keys()` lists, so it is meant for debugging and tests. `account_trie_keys` lists
+// the keys of an account from its storage trie instead.
+fn account_keys(db: &HashStore, address_hash: &H256, combine: Combine) -> HashMap<H256, i32> {
+    db.keys()
+        .into_iter()
+        .filter(|&(_, rc)| rc > 0)
+        .filter_map(|(key, rc)| {
+            let value = db.get(&key)?;
+            let hash = blake2b(&*value);
+            match combine(address_hash, &hash) == key {
+                true => Some((hash, rc)),
+                false => None,
+            }
+        })
+        .collect()
+}
+
+// counts the references to `hash` and to every node below it.
+fn trie_node_keys(db: &HashStore, hash: &H256, keys: &mut HashMap<H256, i32>) -> Result<()> {
+    if *hash == BLAKE2B_NULL_RLP {
+        return Ok(());
+    }
+    let node = db.get(hash).ok_or(Error::Trie(TrieError::IncompleteDatabase(*hash)))?;
+    *keys.entry(*hash).or_insert(0) += 1;
+    inline_node_keys(db, &node, keys)
+}
+
+// descends into the children of a node, following hashes and inline nodes alike.
+fn inline_node_keys(db: &HashStore, node: &[u8], keys: &mut HashMap<H256, i32>) -> Result<()> {
+    let children = match Node::decoded(node) {
+        Node::Extension(_, child) => vec![child],
+        Node::Branch(children, _) => children.to_vec(),
+        Node::Leaf(..) | Node::Empty => Vec::new(),
+    };
+    for child in children {
+        let r = Rlp::new(child);
+        match r.is_data() && r.size() == 32 {
+            true => trie_node_keys(db, &r.as_val(), keys)?,
+            false => inline_node_keys(db, child, keys)?,
+        }
+    }
+    Ok(())
+}
+
+// keys of an account: the nodes of its storage trie, the preimages of its
+// storage keys and its code blobs.
+fn account_trie_keys(db: &HashStore, storage_root: &H256, code_hashes: &[H256]) -> Result<HashMap<H256, i32>> {
+    let mut keys = HashMap::new();
+    trie_node_keys(db, storage_root, &mut keys)?;
+    for item in TrieDB::new(db, storage_root)?.iter()? {
+        let preimage = blake2b(&item?.0);
+        if db.contains(&preimage) {
+            *keys.entry(preimage).or_insert(0) += 1;
+        }
+    }
+    for hash in code_hashes.iter().filter(|hash| **hash != BLAKE2B_EMPTY) {
+        if !db.contains(hash) {
+            return Err(Error::MissingCode(*hash));
+        }
+        *keys.entry(*hash).or_insert(0) += 1;
+    }
+    Ok(keys)
+}
+
 /// A factory for different kinds of account dbs.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Factory {
     /// Mangle hashes based on address.
     Mangled,
     /// Don't mangle hashes.
     Plain,
+    /// Store nodes under the hash of address hash and node hash.
+    Hashed,
 }
 
 impl Default for Factory {
@@ -66,6 +151,7 @@ impl Factory {
         match *self {
             Factory::Mangled => Box::new(AccountDB::from_hash(db, address_hash)),
             Factory::Plain => Box::new(Wrapping(db)),
+            Factory::Hashed => Box::new(AccountDB::hashed(db, address_hash)),
         }
     }
 
@@ -74,7 +160,58 @@ impl Factory {
         match *self {
             Factory::Mangled => Box::new(AccountDBMut::from_hash(db, address_hash)),
             Factory::Plain => Box::new(WrappingMut(db)),
+            Factory::Hashed => Box::new(AccountDBMut::hashed(db, address_hash)),
+        }
+    }
+
+    /// Move the storage trie and code of an account from the layout of this factory
+    /// to the layout of `target`, one reference per use. Returns the number of moved keys.
+    /// `Plain` does not scope keys by account and can only be a migration target.
+    pub fn migrate(
+        &self,
+        target: &Factory,
+        db: &mut HashStore,
+        address_hash: H256,
+        storage_root: &H256,
+        code_hashes: &[H256],
+    ) -> Result<usize>
+    {
+        if self == target {
+            return Ok(0);
+        }
+        if *self == Factory::Plain {
+            return Err(Error::PlainAccountDbMigration);
+        }
+
+        // read everything before the first write, so that a missing value changes nothing.
+        let entries = {
+            let from = self.readonly(&*db, address_hash);
+            account_trie_keys(&*from, storage_root, code_hashes)?
+                .into_iter()
+                .map(|(key, rc)| {
+                    match from.get(&key) {
+                        Some(value) => Ok((key, rc, value)),
+                        None => Err(Error::Trie(TrieError::IncompleteDatabase(key))),
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        {
+            let mut to = target.create(&mut *db, address_hash);
+            for (key, rc, value) in &entries {
+                for _ in 0..*rc {
+                    to.emplace(*key, value.clone());
+                }
+            }
+        }
+        let mut from = self.create(&mut *db, address_hash);
+        for (key, rc, _) in &entries {
+            for _ in 0..*rc {
+                from.remove(key);
+            }
         }
+        trace!(target: "account", "migrated {} keys of {} from {:?} to {:?}", entries.len(), address_hash, self, target);
+        Ok(entries.len())
     }
 }
 
@@ -84,6 +221,7 @@ impl Factory {
 pub struct AccountDB<'db> {
     db: &'db HashStore,
     address_hash: H256,
+    combine: Combine,
 }
 
 impl<'db> AccountDB<'db> {
@@ -92,38 +230,49 @@ impl<'db> AccountDB<'db> {
         AccountDB {
             db: db,
             address_hash: address_hash,
+            combine: combine_key,
+        }
+    }
+
+    /// Create a new AccountDB from an address' hash, using collision-resistant key derivation.
+    pub fn hashed(db: &'db HashStore, address_hash: H256) -> Self {
+        AccountDB {
+            db: db,
+            address_hash: address_hash,
+            combine: hash_key,
         }
     }
 }
 
 impl<'db> HashStore for AccountDB<'db> {
-    fn keys(&self) -> HashMap<H256, i32> { unimplemented!() }
+    fn keys(&self) -> HashMap<H256, i32> { account_keys(self.db, &self.address_hash, self.combine) }
 
     fn get(&self, key: &H256) -> Option<DBValue> {
         if key == &BLAKE2B_NULL_RLP {
             return Some(DBValue::from_slice(&NULL_RLP));
         }
-        self.db.get(&combine_key(&self.address_hash, key))
+        self.db.get(&(self.combine)(&self.address_hash, key))
     }
 
     fn contains(&self, key: &H256) -> bool {
         if key == &BLAKE2B_NULL_RLP {
             return true;
         }
-        self.db.contains(&combine_key(&self.address_hash, key))
+        self.db.contains(&(self.combine)(&self.address_hash, key))
     }
 
-    fn insert(&mut self, _value: &[u8]) -> H256 { unimplemented!() }
+    fn insert(&mut self, _value: &[u8]) -> H256 { panic!("AccountDB is read-only") }
 
-    fn emplace(&mut self, _key: H256, _value: DBValue) { unimplemented!() }
+    fn emplace(&mut self, _key: H256, _value: DBValue) { panic!("AccountDB is read-only") }
 
-    fn remove(&mut self, _key: &H256) { unimplemented!() }
+    fn remove(&mut self, _key: &H256) { panic!("AccountDB is read-only") }
 }
 
 /// DB backend wrapper for Account trie
 pub struct AccountDBMut<'db> {
     db: &'db mut HashStore,
     address_hash: H256,
+    combine: Combine,
 }
 
 impl<'db> AccountDBMut<'db> {
@@ -138,6 +287,16 @@ impl<'db> AccountDBMut<'db> {
         AccountDBMut {
             db: db,
             address_hash: address_hash,
+            combine: combine_key,
+        }
+    }
+
+    /// Create a new AccountDB from an address' hash, using collision-resistant key derivation.
+    pub fn hashed(db: &'db mut HashStore, address_hash: H256) -> Self {
+        AccountDBMut {
+            db: db,
+            address_hash: address_hash,
+            combine: hash_key,
         }
     }
 
@@ -146,25 +305,26 @@ impl<'db> AccountDBMut<'db> {
         AccountDB {
             db: self.db,
             address_hash: self.address_hash.clone(),
+            combine: self.combine,
         }
     }
 }
 
 impl<'db> HashStore for AccountDBMut<'db> {
-    fn keys(&self) -> HashMap<H256, i32> { unimplemented!() }
+    fn keys(&self) -> HashMap<H256, i32> { account_keys(self.db, &self.address_hash, self.combine) }
 
     fn get(&self, key: &H256) -> Option<DBValue> {
         if key == &BLAKE2B_NULL_RLP {
             return Some(DBValue::from_slice(&NULL_RLP));
         }
-        self.db.get(&combine_key(&self.address_hash, key))
+        self.db.get(&(self.combine)(&self.address_hash, key))
     }
 
     fn contains(&self, key: &H256) -> bool {
         if key == &BLAKE2B_NULL_RLP {
             return true;
         }
-        self.db.contains(&combine_key(&self.address_hash, key))
+        self.db.contains(&(self.combine)(&self.address_hash, key))
     }
 
     fn insert(&mut self, value: &[u8]) -> H256 {
@@ -172,7 +332,7 @@ impl<'db> HashStore for AccountDBMut<'db> {
             return BLAKE2B_NULL_RLP.clone();
         }
         let k = blake2b(value);
-        let ak = combine_key(&self.address_hash, &k);
+        let ak = (self.combine)(&self.address_hash, &k);
         self.db.emplace(ak, DBValue::from_slice(value));
         k
     }
@@ -181,7 +341,7 @@ impl<'db> HashStore for AccountDBMut<'db> {
         if key == BLAKE2B_NULL_RLP {
             return;
         }
-        let key = combine_key(&self.address_hash, &key);
+        let key = (self.combine)(&self.address_hash, &key);
         self.db.emplace(key, value)
     }
 
@@ -189,7 +349,7 @@ impl<'db> HashStore for AccountDBMut<'db> {
         if key == &BLAKE2B_NULL_RLP {
             return;
         }
-        let key = combine_key(&self.address_hash, key);
+        let key = (self.combine)(&self.address_hash, key);
         self.db.remove(&key)
     }
 }
@@ -197,7 +357,7 @@ impl<'db> HashStore for AccountDBMut<'db> {
 struct Wrapping<'db>(&'db HashStore);
 
 impl<'db> HashStore for Wrapping<'db> {
-    fn keys(&self) -> HashMap<H256, i32> { unimplemented!() }
+    fn keys(&self) -> HashMap<H256, i32> { self.0.keys() }
 
     fn get(&self, key: &H256) -> Option<DBValue> {
         if key == &BLAKE2B_NULL_RLP {
@@ -213,17 +373,17 @@ impl<'db> HashStore for Wrapping<'db> {
         self.0.contains(key)
     }
 
-    fn insert(&mut self, _value: &[u8]) -> H256 { unimplemented!() }
+    fn insert(&mut self, _value: &[u8]) -> H256 { panic!("Wrapping is read-only") }
 
-    fn emplace(&mut self, _key: H256, _value: DBValue) { unimplemented!() }
+    fn emplace(&mut self, _key: H256, _value: DBValue) { panic!("Wrapping is read-only") }
 
-    fn remove(&mut self, _key: &H256) { unimplemented!() }
+    fn remove(&mut self, _key: &H256) { panic!("Wrapping is read-only") }
 }
 
 struct WrappingMut<'db>(&'db mut HashStore);
 
 impl<'db> HashStore for WrappingMut<'db> {
-    fn keys(&self) -> HashMap<H256, i32> { unimplemented!() }
+    fn keys(&self) -> HashMap<H256, i32> { self.0.keys() }
 
     fn get(&self, key: &H256) -> Option<DBValue> {
         if key == &BLAKE2B_NULL_RLP {
@@ -260,3 +420,104 @@ impl<'db> HashStore for WrappingMut<'db> {
         self.0.remove(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kvdb::MemoryDB;
+    use trie::{TrieDB, TrieDBMut, Trie, TrieMut};
+
+    fn fill(db: &mut HashStore, root: &mut H256) {
+        let mut trie = TrieDBMut::new(db, root);
+        for i in 0..32u8 {
+            trie.insert(&[i; 32], &[i + 1; 40]).unwrap();
+        }
+    }
+
+    #[test]
+    fn account_keys() {
+        let mut db = MemoryDB::new();
+        let (a, b) = (blake2b(Address::from(1)), blake2b(Address::from(2)));
+        let mut root_a = H256::default();
+        let mut root_b = H256::default();
+        fill(&mut AccountDBMut::from_hash(&mut db, a), &mut root_a);
+        fill(&mut AccountDBMut::hashed(&mut db, b), &mut root_b);
+        let code = AccountDBMut::from_hash(&mut db, a).insert(&[0x60; 64]);
+
+        let keys_a = AccountDB::from_hash(&db, a).keys();
+        let keys_b = AccountDB::hashed(&db, b).keys();
+        assert_eq!(keys_a.len() + keys_b.len(), db.keys().len());
+        assert!(keys_a.contains_key(&root_a));
+        assert!(keys_a.contains_key(&code));
+        assert!(keys_b.contains_key(&root_b));
+        assert!(AccountDB::from_hash(&db, b).keys().is_empty());
+        assert_eq!(Wrapping(&db).keys(), db.keys());
+    }
+
+    #[test]
+    #[should_panic(expected = "AccountDB is read-only")]
+    fn readonly_account_db() {
+        let db = MemoryDB::new();
+        Factory::Mangled.readonly(&db, H256::default()).insert(&[0x01]);
+    }
+
+    #[test]
+    fn hashed_keys_do_not_collide() {
+        // address hashes that only differ in the first 12 bytes mangle to the same keys.
+        let a = H256::from(1);
+        let mut b = a;
+        b[0] = 0xff;
+        let key = blake2b([0x01u8]);
+        assert_eq!(combine_key(&a, &key), combine_key(&b, &key));
+        assert!(hash_key(&a, &key) != hash_key(&b, &key));
+    }
+
+    #[test]
+    fn migrate_mangled_to_hashed() {
+        let mut db = MemoryDB::new();
+        let address_hash = blake2b(Address::from(1));
+        let other_hash = blake2b(Address::from(2));
+        let mut root = H256::default();
+        let mut other_root = H256::default();
+        fill(&mut AccountDBMut::from_hash(&mut db, address_hash), &mut root);
+        fill(&mut AccountDBMut::from_hash(&mut db, other_hash), &mut other_root);
+        let code = AccountDBMut::from_hash(&mut db, address_hash).insert(&[0x60; 64]);
+        let count = AccountDB::from_hash(&db, address_hash).keys().len();
+
+        assert_eq!(
+            Factory::Mangled.migrate(&Factory::Hashed, &mut db, address_hash, &root, &[code, BLAKE2B_EMPTY]),
+            Ok(count)
+        );
+        assert!(AccountDB::from_hash(&db, address_hash).keys().is_empty());
+        assert_eq!(AccountDB::hashed(&db, address_hash).keys().len(), count);
+        assert_eq!(AccountDB::from_hash(&db, other_hash).keys().len(), count - 1);
+
+        let account_db = Factory::Hashed.readonly(&db, address_hash);
+        let trie = TrieDB::new(&*account_db, &root).unwrap();
+        assert_eq!(trie.get(&[7u8; 32]).unwrap().unwrap().to_vec(), vec![8u8; 40]);
+        assert!(account_db.contains(&code));
+    }
+
+    #[test]
+    fn migrate_errors() {
+        let mut db = MemoryDB::new();
+        let address_hash = blake2b(Address::from(1));
+        let mut root = H256::default();
+        fill(&mut AccountDBMut::from_hash(&mut db, address_hash), &mut root);
+
+        assert_eq!(
+            Factory::Plain.migrate(&Factory::Hashed, &mut db, address_hash, &root, &[]),
+            Err(Error::PlainAccountDbMigration)
+        );
+        let code = blake2b([0x60u8]);
+        assert_eq!(
+            Factory::Mangled.migrate(&Factory::Hashed, &mut db, address_hash, &root, &[code]),
+            Err(Error::MissingCode(code))
+        );
+        assert_eq!(
+            Factory::Hashed.migrate(&Factory::Mangled, &mut db, address_hash, &root, &[]),
+            Err(Error::Trie(TrieError::IncompleteDatabase(root)))
+        );
+        assert_eq!(AccountDB::from_hash(&db, address_hash).keys().len(), db.keys().len());
+    }
+}
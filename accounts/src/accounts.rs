@@ -1,16 +1,19 @@
-use lru_cache::LruCache;
-use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
+use parking_lot::RwLock;
+use std::collections::{HashMap, BTreeMap, btree_map};
 use std::sync::Arc;
 
 use aion_types::{H128, U128, H256, U256, Address};
 use bytes::{Bytes, ToPretty};
-use generic::{Account, Filth};
+use generic::{Account, AccType, Filth};
+use cache::StorageCache;
+use state_cache::StateCache;
+use access::{AccessKind, AccessRecorder, ReadWriteTracker, StorageKey};
 use traits::CommonAccount;
+use error::{self, Error};
 use blake2b::{BLAKE2B_EMPTY, BLAKE2B_NULL_RLP, blake2b};
 use rlp::*;
 use trie;
-use trie::{Trie, SecTrieDB, TrieFactory};
+use trie::{Trie, TrieDB, TrieError, SecTrieDB, TrieFactory};
 
 use kvdb::{DBValue, HashStore};
 
@@ -29,13 +32,13 @@ pub struct BasicAccount {
 
 const STORAGE_CACHE_ITEMS: usize = 8192;
 
-type FVMCache = (RefCell<LruCache<H128, H128>>, RefCell<LruCache<H128, H256>>);
+type FVMCache = (StorageCache<H128, H128>, StorageCache<H128, H256>);
 type FVMStorageChange = (HashMap<H128, H128>, HashMap<H128, H256>);
 pub type FVMAccount = Account<FVMCache, FVMStorageChange>;
 
 impl FVMAccount {
     fn empty_storage_cache() -> FVMCache {
-        (RefCell::new(LruCache::new(STORAGE_CACHE_ITEMS)), RefCell::new(LruCache::new(STORAGE_CACHE_ITEMS)))
+        (StorageCache::new(STORAGE_CACHE_ITEMS), StorageCache::new(STORAGE_CACHE_ITEMS))
     }
 
     fn empty_storage_change() -> FVMStorageChange {
@@ -55,8 +58,23 @@ impl From<BasicAccount> for FVMAccount {
             code_size: None,
             code_cache: Arc::new(vec![]),
             code_filth: Filth::Clean,
-            address_hash: Cell::new(None),
+            address_hash: RwLock::new(None),
             empty_but_commit: false,
+            account_type: match basic.code_hash == BLAKE2B_EMPTY {
+                true => AccType::Normal,
+                false => AccType::FVM,
+            },
+            transformed_code_hash: BLAKE2B_EMPTY,
+            transformed_code_size: None,
+            transformed_code_cache: Arc::new(vec![]),
+            transformed_code_filth: Filth::Clean,
+            objectgraph_hash: BLAKE2B_EMPTY,
+            objectgraph_size: None,
+            objectgraph_cache: Arc::new(vec![]),
+            objectgraph_filth: Filth::Clean,
+            access_recorder: None,
+            access_tracker: None,
+            filth: Filth::Clean,
         }
     }
 }
@@ -73,8 +91,20 @@ impl FVMAccount {
             code_cache: Arc::new(vec![]),
             code_size: None,
             code_filth: Filth::Clean,
-            address_hash: Cell::new(None),
+            address_hash: RwLock::new(None),
             empty_but_commit: false,
+            account_type: AccType::FVM,
+            transformed_code_hash: BLAKE2B_EMPTY,
+            transformed_code_size: None,
+            transformed_code_cache: Arc::new(vec![]),
+            transformed_code_filth: Filth::Clean,
+            objectgraph_hash: BLAKE2B_EMPTY,
+            objectgraph_size: None,
+            objectgraph_cache: Arc::new(vec![]),
+            objectgraph_filth: Filth::Clean,
+            access_recorder: None,
+            access_tracker: None,
+            filth: Filth::Dirty,
         }
     }
 
@@ -89,8 +119,20 @@ impl FVMAccount {
             code_cache: Arc::new(vec![]),
             code_size: Some(0),
             code_filth: Filth::Clean,
-            address_hash: Cell::new(None),
+            address_hash: RwLock::new(None),
             empty_but_commit: false,
+            account_type: AccType::Normal,
+            transformed_code_hash: BLAKE2B_EMPTY,
+            transformed_code_size: None,
+            transformed_code_cache: Arc::new(vec![]),
+            transformed_code_filth: Filth::Clean,
+            objectgraph_hash: BLAKE2B_EMPTY,
+            objectgraph_size: None,
+            objectgraph_cache: Arc::new(vec![]),
+            objectgraph_filth: Filth::Clean,
+            access_recorder: None,
+            access_tracker: None,
+            filth: Filth::Dirty,
         }
     }
 
@@ -105,6 +147,9 @@ impl FVMAccount {
         db: &mut HashStore,
     ) -> trie::Result<()>
     {
+        if self.storage_is_clean() {
+            return Ok(());
+        }
         let mut t = trie_factory.from_existing(db, &mut self.storage_root)?;
         for (k, v) in self.storage_changes.0.drain() {
             // cast key and value to trait type,
@@ -114,7 +159,7 @@ impl FVMAccount {
                 false => t.insert(&k, &encode(&U128::from(&*v)))?,
             };
 
-            self.storage_cache.0.borrow_mut().insert(k, v);
+            self.storage_cache.0.lock().insert(k, v);
         }
 
         for (k, v) in self.storage_changes.1.drain() {
@@ -125,7 +170,7 @@ impl FVMAccount {
                 false => t.insert(&k, &encode(&v))?,
             };
 
-            self.storage_cache.1.borrow_mut().insert(k, v);
+            self.storage_cache.1.lock().insert(k, v);
         }
 
         Ok(())
@@ -148,8 +193,20 @@ impl FVMAccount {
             code_size: self.code_size.clone(),
             code_cache: self.code_cache.clone(),
             code_filth: self.code_filth,
-            address_hash: self.address_hash.clone(),
+            address_hash: RwLock::new(*self.address_hash.read()),
             empty_but_commit: self.empty_but_commit.clone(),
+            account_type: self.account_type,
+            transformed_code_hash: self.transformed_code_hash,
+            transformed_code_size: self.transformed_code_size,
+            transformed_code_cache: self.transformed_code_cache.clone(),
+            transformed_code_filth: self.transformed_code_filth,
+            objectgraph_hash: self.objectgraph_hash,
+            objectgraph_size: self.objectgraph_size,
+            objectgraph_cache: self.objectgraph_cache.clone(),
+            objectgraph_filth: self.objectgraph_filth,
+            access_recorder: self.access_recorder.clone(),
+            access_tracker: self.access_tracker.clone(),
+            filth: self.filth,
         }
     }
 
@@ -172,13 +229,15 @@ impl FVMAccount {
         self.code_cache = other.code_cache;
         self.code_size = other.code_size;
         self.address_hash = other.address_hash;
+        self.account_type = other.account_type;
+        self.filth = other.filth;
 
-        let mut cache = self.storage_cache.0.borrow_mut();
+        let mut cache = self.storage_cache.0.lock();
         for (k, v) in other.storage_cache.0.into_inner() {
             cache.insert(k.clone(), v.clone()); //TODO: cloning should not be required here
         }
 
-        let mut cache = self.storage_cache.1.borrow_mut();
+        let mut cache = self.storage_cache.1.lock();
         for (k, v) in other.storage_cache.1.into_inner() {
             cache.insert(k.clone(), v.clone()); //TODO: cloning should not be required here
         }
@@ -200,8 +259,18 @@ impl AVMAccount {
         self.code_cache = other.code_cache;
         self.code_size = other.code_size;
         self.address_hash = other.address_hash;
-
-        let mut cache = self.storage_cache.borrow_mut();
+        self.account_type = other.account_type;
+        self.filth = other.filth;
+        self.transformed_code_hash = other.transformed_code_hash;
+        self.transformed_code_filth = other.transformed_code_filth;
+        self.transformed_code_cache = other.transformed_code_cache;
+        self.transformed_code_size = other.transformed_code_size;
+        self.objectgraph_hash = other.objectgraph_hash;
+        self.objectgraph_filth = other.objectgraph_filth;
+        self.objectgraph_cache = other.objectgraph_cache;
+        self.objectgraph_size = other.objectgraph_size;
+
+        let mut cache = self.storage_cache.lock();
         for (k, v) in other.storage_cache.into_inner() {
             cache.insert(k.clone(), v.clone()); //TODO: cloning should not be required here
         }
@@ -221,19 +290,34 @@ impl From<BasicAccount> for AVMAccount {
             code_size: None,
             code_cache: Arc::new(vec![]),
             code_filth: Filth::Clean,
-            address_hash: Cell::new(None),
+            address_hash: RwLock::new(None),
             empty_but_commit: false,
+            account_type: match basic.code_hash == BLAKE2B_EMPTY {
+                true => AccType::Normal,
+                false => AccType::AVM,
+            },
+            transformed_code_hash: BLAKE2B_EMPTY,
+            transformed_code_size: None,
+            transformed_code_cache: Arc::new(vec![]),
+            transformed_code_filth: Filth::Clean,
+            objectgraph_hash: BLAKE2B_EMPTY,
+            objectgraph_size: None,
+            objectgraph_cache: Arc::new(vec![]),
+            objectgraph_filth: Filth::Clean,
+            access_recorder: None,
+            access_tracker: None,
+            filth: Filth::Clean,
         }
     }
 }
 
-type AVMCache = RefCell<LruCache<Bytes, Bytes>>;
+type AVMCache = StorageCache<Bytes, Bytes>;
 type AVMStorageChange = HashMap<Bytes, Bytes>;
 pub type AVMAccount = Account<AVMCache, AVMStorageChange>;
 
 impl AVMAccount {
     fn empty_storage_cache() -> AVMCache {
-        RefCell::new(LruCache::new(STORAGE_CACHE_ITEMS))
+        StorageCache::new(STORAGE_CACHE_ITEMS)
     }
 
     fn empty_storage_change() -> AVMStorageChange {
@@ -251,11 +335,119 @@ impl AVMAccount {
             code_cache: Arc::new(vec![]),
             code_size: Some(0),
             code_filth: Filth::Clean,
-            address_hash: Cell::new(None),
+            address_hash: RwLock::new(None),
+            empty_but_commit: false,
+            account_type: AccType::Normal,
+            transformed_code_hash: BLAKE2B_EMPTY,
+            transformed_code_size: None,
+            transformed_code_cache: Arc::new(vec![]),
+            transformed_code_filth: Filth::Clean,
+            objectgraph_hash: BLAKE2B_EMPTY,
+            objectgraph_size: None,
+            objectgraph_cache: Arc::new(vec![]),
+            objectgraph_filth: Filth::Clean,
+            access_recorder: None,
+            access_tracker: None,
+            filth: Filth::Dirty,
+        }
+    }
+
+    pub fn new_contract(balance: U256, nonce: U256) -> Self {
+        Self {
+            balance: balance,
+            nonce: nonce,
+            storage_root: BLAKE2B_NULL_RLP,
+            storage_cache: Self::empty_storage_cache(),
+            storage_changes: Self::empty_storage_change(),
+            code_hash: BLAKE2B_EMPTY,
+            code_cache: Arc::new(vec![]),
+            code_size: None,
+            code_filth: Filth::Clean,
+            address_hash: RwLock::new(None),
             empty_but_commit: false,
+            account_type: AccType::AVM,
+            transformed_code_hash: BLAKE2B_EMPTY,
+            transformed_code_size: None,
+            transformed_code_cache: Arc::new(vec![]),
+            transformed_code_filth: Filth::Clean,
+            objectgraph_hash: BLAKE2B_EMPTY,
+            objectgraph_size: None,
+            objectgraph_cache: Arc::new(vec![]),
+            objectgraph_filth: Filth::Clean,
+            access_recorder: None,
+            access_tracker: None,
+            filth: Filth::Dirty,
         }
     }
 
+    /// Set the transformed code deployed by the AVM. The original code is set with `init_code`.
+    pub fn init_transformed_code(&mut self, code: Bytes) {
+        self.filth = Filth::Dirty;
+        self.transformed_code_hash = blake2b(&code);
+        self.transformed_code_cache = Arc::new(code);
+        self.transformed_code_size = Some(self.transformed_code_cache.len());
+        self.transformed_code_filth = Filth::Dirty;
+    }
+
+    /// Hash of the transformed code.
+    pub fn transformed_code_hash(&self) -> H256 { self.transformed_code_hash }
+
+    /// Transformed code, if cached.
+    pub fn transformed_code(&self) -> Option<Arc<Bytes>> {
+        if self.transformed_code_cache.is_empty() {
+            return None;
+        }
+
+        Some(self.transformed_code_cache.clone())
+    }
+
+    /// Size of the transformed code, if known.
+    pub fn transformed_code_size(&self) -> Option<usize> { self.transformed_code_size }
+
+    /// Load the transformed code from `db` unless it is cached.
+    pub fn cache_transformed_code(&mut self, db: &HashStore) -> Option<Arc<Bytes>> {
+        cache_blob(
+            db,
+            &self.transformed_code_hash,
+            &mut self.transformed_code_cache,
+            &mut self.transformed_code_size,
+        )
+    }
+
+    /// Replace the serialized object graph. Written to the db by `commit_code`.
+    pub fn set_objectgraph(&mut self, data: Bytes) {
+        self.filth = Filth::Dirty;
+        self.objectgraph_hash = blake2b(&data);
+        self.objectgraph_cache = Arc::new(data);
+        self.objectgraph_size = Some(self.objectgraph_cache.len());
+        self.objectgraph_filth = Filth::Dirty;
+    }
+
+    /// Hash of the serialized object graph.
+    pub fn objectgraph_hash(&self) -> H256 { self.objectgraph_hash }
+
+    /// Serialized object graph, if cached.
+    pub fn objectgraph(&self) -> Option<Arc<Bytes>> {
+        if self.objectgraph_cache.is_empty() {
+            return None;
+        }
+
+        Some(self.objectgraph_cache.clone())
+    }
+
+    /// Size of the serialized object graph, if known.
+    pub fn objectgraph_size(&self) -> Option<usize> { self.objectgraph_size }
+
+    /// Load the object graph from `db` unless it is cached.
+    pub fn cache_objectgraph(&mut self, db: &HashStore) -> Option<Arc<Bytes>> {
+        cache_blob(
+            db,
+            &self.objectgraph_hash,
+            &mut self.objectgraph_cache,
+            &mut self.objectgraph_size,
+        )
+    }
+
     /// Determine whether there are any un-`commit()`-ed storage-setting operations.
     fn storage_is_clean(&self) -> bool {
         self.storage_changes.is_empty() 
@@ -273,8 +465,20 @@ impl AVMAccount {
             code_size: self.code_size.clone(),
             code_cache: self.code_cache.clone(),
             code_filth: self.code_filth,
-            address_hash: self.address_hash.clone(),
+            address_hash: RwLock::new(*self.address_hash.read()),
             empty_but_commit: self.empty_but_commit,
+            account_type: self.account_type,
+            transformed_code_hash: self.transformed_code_hash,
+            transformed_code_size: self.transformed_code_size,
+            transformed_code_cache: self.transformed_code_cache.clone(),
+            transformed_code_filth: self.transformed_code_filth,
+            objectgraph_hash: self.objectgraph_hash,
+            objectgraph_size: self.objectgraph_size,
+            objectgraph_cache: self.objectgraph_cache.clone(),
+            objectgraph_filth: self.objectgraph_filth,
+            access_recorder: self.access_recorder.clone(),
+            access_tracker: self.access_tracker.clone(),
+            filth: self.filth,
         }
     }
 
@@ -285,6 +489,9 @@ impl AVMAccount {
         db: &mut HashStore,
     ) -> trie::Result<()>
     {
+        if self.storage_is_clean() {
+            return Ok(());
+        }
         let mut t = trie_factory.from_existing(db, &mut self.storage_root)?;
         for (k, v) in self.storage_changes.drain() {
             // cast key and value to trait type,
@@ -301,32 +508,137 @@ impl AVMAccount {
                 false => t.insert(&k, &encode(&v))?,
             };
 
-            self.storage_cache.borrow_mut().insert(k, v);
+            self.storage_cache.lock().insert(k, v);
         }
 
         Ok(())
     }
 }
 
+/// Account fields decoded from RLP.
+#[derive(Debug, PartialEq)]
+pub struct DecodedAccount {
+    /// Nonce, balance, storage root and code hash.
+    pub basic: BasicAccount,
+    /// Account type marker, if persisted.
+    pub account_type: Option<AccType>,
+    /// Transformed code hash and object graph hash of AVM accounts, if persisted.
+    pub avm_hashes: Option<(H256, H256)>,
+}
+
+/// Decode account RLP. Accepts the legacy 4-item layout, the 5-item layout
+/// carrying the account type marker and the 7-item AVM layout.
+pub fn decode_account(rlp: &[u8]) -> Result<DecodedAccount, DecoderError> {
+    let r = UntrustedRlp::new(rlp);
+    let item_count = r.item_count()?;
+    let account_type = match item_count {
+        4 => None,
+        5 | 7 => {
+            let marker: u8 = r.val_at(4)?;
+            match AccType::from_u8(marker) {
+                Some(account_type) => Some(account_type),
+                None => return Err(DecoderError::Custom("invalid account type marker")),
+            }
+        }
+        _ => return Err(DecoderError::RlpIncorrectListLen),
+    };
+    let avm_hashes = match item_count {
+        7 => Some((r.val_at(5)?, r.val_at(6)?)),
+        _ => None,
+    };
+    let basic = BasicAccount {
+        nonce: r.val_at(0)?,
+        balance: r.val_at(1)?,
+        storage_root: r.val_at(2)?,
+        code_hash: r.val_at(3)?,
+    };
+    Ok(DecodedAccount {
+        basic: basic,
+        account_type: account_type,
+        avm_hashes: avm_hashes,
+    })
+}
+
+type Tracker = Option<(Address, Arc<ReadWriteTracker>)>;
+
+fn track_account(tracker: &Tracker, kind: AccessKind) {
+    if let Some((ref address, ref tracker)) = *tracker {
+        tracker.record(kind, address);
+    }
+}
+
+fn track_storage<F: FnOnce() -> StorageKey>(tracker: &Tracker, kind: AccessKind, key: F) {
+    if let Some((ref address, ref tracker)) = *tracker {
+        tracker.record_storage(kind, address, key());
+    }
+}
+
+/// Write a content-addressed blob (code, transformed code or object graph) if it is dirty.
+fn commit_blob(db: &mut HashStore, hash: &H256, cache: &Arc<Bytes>, size: &mut Option<usize>, filth: &mut Filth) {
+    match (*filth == Filth::Dirty, cache.is_empty()) {
+        (true, true) => {
+            *size = Some(0);
+            *filth = Filth::Clean;
+        }
+        (true, false) => {
+            db.emplace(*hash, DBValue::from_slice(cache));
+            *size = Some(cache.len());
+            *filth = Filth::Clean;
+        }
+        (false, _) => {}
+    }
+}
+
+/// Load a content-addressed blob into `cache` unless it is already there.
+fn cache_blob(db: &HashStore, hash: &H256, cache: &mut Arc<Bytes>, size: &mut Option<usize>) -> Option<Arc<Bytes>> {
+    if !cache.is_empty() || *hash == BLAKE2B_EMPTY {
+        return Some(cache.clone());
+    }
+    match db.get(hash) {
+        Some(x) => {
+            *size = Some(x.len());
+            *cache = Arc::new(x.into_vec());
+            Some(cache.clone())
+        }
+        _ => {
+            warn!(target: "account", "Failed reverse get of {}", hash);
+            None
+        }
+    }
+}
+
 macro_rules! impl_account {
-    ($T: ty, $fixed_strg: expr) => {
+    ($T: ty, $fixed_strg: expr, $acc_type: expr) => {
         impl CommonAccount for $T {
             fn from_rlp(rlp: &[u8]) -> $T {
-                let basic: BasicAccount = ::rlp::decode(rlp);
-                basic.into()
+                let decoded = decode_account(rlp).expect("invalid account rlp");
+                let mut account: $T = decoded.basic.into();
+                if let Some(account_type) = decoded.account_type {
+                    account.account_type = account_type;
+                }
+                if let Some((transformed_code_hash, objectgraph_hash)) = decoded.avm_hashes {
+                    account.transformed_code_hash = transformed_code_hash;
+                    account.objectgraph_hash = objectgraph_hash;
+                }
+                account
             }
 
             fn init_code(&mut self, code: Bytes) {
+                track_account(&self.access_tracker, AccessKind::Write);
+                self.filth = Filth::Dirty;
                 self.code_hash = blake2b(&code);
                 self.code_cache = Arc::new(code);
                 self.code_size = Some(self.code_cache.len());
                 self.code_filth = Filth::Dirty;
+                self.account_type = $acc_type;
             }
 
             fn reset_code(&mut self, code: Bytes) {
                 self.init_code(code);
             }
 
+            fn account_type(&self) -> AccType {self.account_type}
+
             fn balance(&self) -> &U256 {&self.balance}
 
             fn nonce(&self) -> &U256 {&self.nonce}
@@ -334,10 +646,10 @@ macro_rules! impl_account {
             fn code_hash(&self) -> H256 {self.code_hash.clone()}
 
             fn address_hash(&self, address: &Address) -> H256 {
-                let hash = self.address_hash.get();
+                let hash = *self.address_hash.read();
                 hash.unwrap_or_else(|| {
                     let hash = blake2b(address);
-                    self.address_hash.set(Some(hash.clone()));
+                    *self.address_hash.write() = Some(hash.clone());
                     hash
                 })
             }
@@ -384,6 +696,23 @@ macro_rules! impl_account {
                 }
             }
 
+            fn cache_code_shared(&mut self, cache: &StateCache, db: &HashStore) -> Option<Arc<Bytes>> {
+                if self.is_cached() {
+                    return Some(self.code_cache.clone());
+                }
+
+                if let Some(code) = cache.code(&self.code_hash) {
+                    self.cache_given_code(code.clone());
+                    return Some(code);
+                }
+
+                let code = self.cache_code(db);
+                if let Some(ref code) = code {
+                    cache.insert_code(self.code_hash, code.clone());
+                }
+                code
+            }
+
             fn cache_given_code(&mut self, code: Arc<Bytes>) {
                 trace!(
                     target: "account",
@@ -423,13 +752,13 @@ macro_rules! impl_account {
             }
 
             fn is_empty(&self) -> bool {
-                assert!(
-                    self.storage_is_clean(),
-                    "Account::is_empty() may only legally be called when storage is clean."
-                );
-                self.is_null() && self.storage_root == BLAKE2B_NULL_RLP
+                self.storage_is_clean() && self.is_null() && self.storage_root == BLAKE2B_NULL_RLP
             }
 
+            fn is_empty_but_commit(&self) -> bool { self.empty_but_commit }
+
+            fn set_empty_but_commit(&mut self) { self.empty_but_commit = true; }
+
             fn is_null(&self) -> bool {
                 self.balance.is_zero() && self.nonce.is_zero() && self.code_hash == BLAKE2B_EMPTY
             }
@@ -446,19 +775,62 @@ macro_rules! impl_account {
                 }
             }
 
-            fn inc_nonce(&mut self) {self.nonce = self.nonce + U256::from(1u8);}
+            fn inc_nonce(&mut self) {
+                track_account(&self.access_tracker, AccessKind::Write);
+                self.filth = Filth::Dirty;
+                self.nonce = self.nonce + U256::from(1u8);
+            }
 
             /// Increase account balance.
-            fn add_balance(&mut self, x: &U256) {self.balance = self.balance + *x;}
+            fn add_balance(&mut self, x: &U256) {
+                track_account(&self.access_tracker, AccessKind::Write);
+                self.filth = Filth::Dirty;
+                self.balance = self.balance + *x;
+            }
 
             /// Decrease account balance.
             /// Panics if balance is less than `x`
             fn sub_balance(&mut self, x: &U256) {
+                track_account(&self.access_tracker, AccessKind::Write);
+                self.filth = Filth::Dirty;
                 assert!(self.balance >= *x);
                 self.balance = self.balance - *x;
             }
 
+            // The checked operations record a write only if they succeed; the read is
+            // recorded when the tracker is attached.
+            fn checked_inc_nonce(&mut self) -> error::Result<()> {
+                let nonce = self.nonce.checked_add(U256::from(1u8)).ok_or(Error::NonceOverflow)?;
+                track_account(&self.access_tracker, AccessKind::Write);
+                self.nonce = nonce;
+                self.filth = Filth::Dirty;
+                Ok(())
+            }
+
+            fn checked_add_balance(&mut self, x: &U256) -> error::Result<()> {
+                let balance = self.balance.checked_add(*x).ok_or(Error::BalanceOverflow {
+                    balance: self.balance,
+                    amount: *x,
+                })?;
+                track_account(&self.access_tracker, AccessKind::Write);
+                self.balance = balance;
+                self.filth = Filth::Dirty;
+                Ok(())
+            }
+
+            fn checked_sub_balance(&mut self, x: &U256) -> error::Result<()> {
+                let balance = self.balance.checked_sub(*x).ok_or(Error::BalanceUnderflow {
+                    balance: self.balance,
+                    amount: *x,
+                })?;
+                track_account(&self.access_tracker, AccessKind::Write);
+                self.balance = balance;
+                self.filth = Filth::Dirty;
+                Ok(())
+            }
+
             /// Commit any unsaved code. `code_hash` will always return the hash of the `code_cache` after this.
+            /// Transformed code and object graph of AVM accounts are committed along with it.
             fn commit_code(&mut self, db: &mut HashStore) {
                 trace!(
                     target: "account",
@@ -467,61 +839,278 @@ macro_rules! impl_account {
                     self.code_filth == Filth::Dirty,
                     self.code_cache.is_empty()
                 );
-                match (self.code_filth == Filth::Dirty, self.code_cache.is_empty()) {
-                    (true, true) => {
-                        self.code_size = Some(0);
-                        self.code_filth = Filth::Clean;
-                    }
-                    (true, false) => {
-                        db.emplace(
-                            self.code_hash.clone(),
-                            DBValue::from_slice(&*self.code_cache),
-                        );
-                        self.code_size = Some(self.code_cache.len());
-                        self.code_filth = Filth::Clean;
-                    }
-                    (false, _) => {}
-                }
+                commit_blob(db, &self.code_hash, &self.code_cache, &mut self.code_size, &mut self.code_filth);
+                commit_blob(
+                    db,
+                    &self.transformed_code_hash,
+                    &self.transformed_code_cache,
+                    &mut self.transformed_code_size,
+                    &mut self.transformed_code_filth,
+                );
+                commit_blob(
+                    db,
+                    &self.objectgraph_hash,
+                    &self.objectgraph_cache,
+                    &mut self.objectgraph_size,
+                    &mut self.objectgraph_filth,
+                );
             }
 
             /// Export to RLP.
+            /// Normal and FVM accounts keep the legacy 4-item layout, so their
+            /// type follows from the code hash and existing roots do not change.
+            /// AVM accounts carry the type marker, and the AVM hashes once set.
             fn rlp(&self) -> Bytes {
-                let mut stream = RlpStream::new_list(4);
+                let avm = self.transformed_code_hash != BLAKE2B_EMPTY
+                    || self.objectgraph_hash != BLAKE2B_EMPTY;
+                let mut stream = RlpStream::new_list(match (avm, self.account_type) {
+                    (true, _) => 7,
+                    (false, AccType::AVM) => 5,
+                    (false, _) => 4,
+                });
                 stream.append(&self.nonce);
                 stream.append(&self.balance);
                 stream.append(&self.storage_root);
                 stream.append(&self.code_hash);
+                if avm || self.account_type == AccType::AVM {
+                    stream.append(&(self.account_type as u8));
+                }
+                if avm {
+                    stream.append(&self.transformed_code_hash);
+                    stream.append(&self.objectgraph_hash);
+                }
                 stream.out()
             }
 
+            fn is_dirty(&self) -> bool {
+                self.filth == Filth::Dirty
+                    || !self.storage_is_clean()
+                    || self.code_filth == Filth::Dirty
+                    || self.transformed_code_filth == Filth::Dirty
+                    || self.objectgraph_filth == Filth::Dirty
+            }
+
+            fn commit_rlp(&mut self) -> Option<Bytes> {
+                if !self.is_dirty() {
+                    return None;
+                }
+                self.filth = Filth::Clean;
+                Some(self.rlp())
+            }
+
+            fn track_access(&mut self, address: Address, tracker: Arc<ReadWriteTracker>) {
+                tracker.record(AccessKind::Read, &address);
+                self.access_tracker = Some((address, tracker));
+            }
+
             /// Clone account data and dirty storage keys
             fn clone_dirty(&self) -> Self {
                 let mut account = self.clone_basic();
                 account.storage_changes = self.storage_changes.clone();
                 account.code_cache = self.code_cache.clone();
+                account.transformed_code_cache = self.transformed_code_cache.clone();
+                account.objectgraph_cache = self.objectgraph_cache.clone();
                 account
             }
         }
     };
 }
 
-impl_account!(FVMAccount, true);
-impl_account!(AVMAccount, false);
+impl_account!(FVMAccount, true, AccType::FVM);
+impl_account!(AVMAccount, false, AccType::AVM);
+
+/// Account of either VM, dispatched on the type marker in the account RLP.
+#[derive(Debug, Clone)]
+pub enum VMAccount {
+    /// FastVM account, also used for normal accounts.
+    FVM(FVMAccount),
+    /// AVM account.
+    AVM(AVMAccount),
+}
+
+impl From<FVMAccount> for VMAccount {
+    fn from(account: FVMAccount) -> Self { VMAccount::FVM(account) }
+}
+
+impl From<AVMAccount> for VMAccount {
+    fn from(account: AVMAccount) -> Self { VMAccount::AVM(account) }
+}
+
+// wrapper macro for making the match easier to deal with.
+macro_rules! wrapper {
+    ($me: ident, $f_name: ident, $($param: ident),*) => {
+        match *$me {
+            VMAccount::FVM(ref a) => a.$f_name($($param),*),
+            VMAccount::AVM(ref a) => a.$f_name($($param),*),
+        }
+    }
+}
+
+macro_rules! wrapper_mut {
+    ($me: ident, $f_name: ident, $($param: ident),*) => {
+        match *$me {
+            VMAccount::FVM(ref mut a) => a.$f_name($($param),*),
+            VMAccount::AVM(ref mut a) => a.$f_name($($param),*),
+        }
+    }
+}
+
+impl CommonAccount for VMAccount {
+    fn from_rlp(rlp: &[u8]) -> Self {
+        match decode_account(rlp).expect("invalid account rlp").account_type {
+            Some(AccType::AVM) => VMAccount::AVM(AVMAccount::from_rlp(rlp)),
+            _ => VMAccount::FVM(FVMAccount::from_rlp(rlp)),
+        }
+    }
+
+    fn account_type(&self) -> AccType { wrapper!(self, account_type,) }
+
+    fn init_code(&mut self, code: Bytes) { wrapper_mut!(self, init_code, code) }
+
+    fn reset_code(&mut self, code: Bytes) { wrapper_mut!(self, reset_code, code) }
+
+    fn balance(&self) -> &U256 { wrapper!(self, balance,) }
+
+    fn nonce(&self) -> &U256 { wrapper!(self, nonce,) }
+
+    fn code_hash(&self) -> H256 { wrapper!(self, code_hash,) }
 
-#[derive(Debug)]
-enum FVMKey {
+    fn address_hash(&self, address: &Address) -> H256 { wrapper!(self, address_hash, address) }
+
+    fn code(&self) -> Option<Arc<Bytes>> { wrapper!(self, code,) }
+
+    fn code_size(&self) -> Option<usize> { wrapper!(self, code_size,) }
+
+    fn is_cached(&self) -> bool { wrapper!(self, is_cached,) }
+
+    fn cache_code(&mut self, db: &HashStore) -> Option<Arc<Bytes>> {
+        wrapper_mut!(self, cache_code, db)
+    }
+
+    fn cache_code_shared(&mut self, cache: &StateCache, db: &HashStore) -> Option<Arc<Bytes>> {
+        wrapper_mut!(self, cache_code_shared, cache, db)
+    }
+
+    fn cache_given_code(&mut self, code: Arc<Bytes>) { wrapper_mut!(self, cache_given_code, code) }
+
+    fn cache_code_size(&mut self, db: &HashStore) -> bool { wrapper_mut!(self, cache_code_size, db) }
+
+    fn is_empty(&self) -> bool { wrapper!(self, is_empty,) }
+
+    fn is_empty_but_commit(&self) -> bool { wrapper!(self, is_empty_but_commit,) }
+
+    fn set_empty_but_commit(&mut self) { wrapper_mut!(self, set_empty_but_commit,) }
+
+    fn is_null(&self) -> bool { wrapper!(self, is_null,) }
+
+    fn is_basic(&self) -> bool { wrapper!(self, is_basic,) }
+
+    fn storage_root(&self) -> Option<&H256> { wrapper!(self, storage_root,) }
+
+    fn inc_nonce(&mut self) { wrapper_mut!(self, inc_nonce,) }
+
+    fn add_balance(&mut self, x: &U256) { wrapper_mut!(self, add_balance, x) }
+
+    fn sub_balance(&mut self, x: &U256) { wrapper_mut!(self, sub_balance, x) }
+
+    fn checked_inc_nonce(&mut self) -> error::Result<()> { wrapper_mut!(self, checked_inc_nonce,) }
+
+    fn checked_add_balance(&mut self, x: &U256) -> error::Result<()> {
+        wrapper_mut!(self, checked_add_balance, x)
+    }
+
+    fn checked_sub_balance(&mut self, x: &U256) -> error::Result<()> {
+        wrapper_mut!(self, checked_sub_balance, x)
+    }
+
+    fn commit_code(&mut self, db: &mut HashStore) { wrapper_mut!(self, commit_code, db) }
+
+    fn rlp(&self) -> Bytes { wrapper!(self, rlp,) }
+
+    fn is_dirty(&self) -> bool { wrapper!(self, is_dirty,) }
+
+    fn commit_rlp(&mut self) -> Option<Bytes> { wrapper_mut!(self, commit_rlp,) }
+
+    fn track_access(&mut self, address: Address, tracker: Arc<ReadWriteTracker>) {
+        wrapper_mut!(self, track_access, address, tracker)
+    }
+
+    fn clone_dirty(&self) -> Self {
+        match *self {
+            VMAccount::FVM(ref a) => VMAccount::FVM(a.clone_dirty()),
+            VMAccount::AVM(ref a) => VMAccount::AVM(a.clone_dirty()),
+        }
+    }
+}
+
+/// Storage key of a FastVM account.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FVMKey {
+    /// Key of a 128-bit storage value.
     Normal(H128),
+    /// Key of a 256-bit storage value.
     Wide(H128),
 }
 
-#[derive(Debug, PartialEq)]
-enum FVMValue {
+/// Storage value of a FastVM account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FVMValue {
+    /// 128-bit storage value.
     Normal(H128),
+    /// 256-bit storage value.
     Long(H256),
 }
 
+/// Read back all committed entries of a storage trie.
+/// Keys are resolved through the preimages kept by a fat trie (see `FatDBMut`),
+/// so a storage trie committed through a secure trie yields `IncompleteDatabase`.
+fn committed_storage(db: &HashStore, root: &H256) -> trie::Result<Vec<(Bytes, DBValue)>> {
+    let t = TrieDB::new(db, root)?;
+    let mut entries = Vec::new();
+    for item in t.iter()? {
+        let (hash, value) = item?;
+        let aux_hash = blake2b(&hash);
+        match db.get(&aux_hash) {
+            Some(key) => entries.push((key.into_vec(), value)),
+            None => return Err(Box::new(TrieError::IncompleteDatabase(aux_hash))),
+        }
+    }
+    Ok(entries)
+}
+
 impl FVMAccount {
+    /// Iterate over the effective storage of this account, ordered by key.
+    ///
+    /// Committed entries require the storage trie to be built by a fat `TrieFactory`.
+    /// Committed values that fit in 128 bits are reported as `FVMValue::Normal`.
+    /// Pending `storage_changes` take precedence and zero values are left out.
+    pub fn storage_iter(&self, db: &HashStore) -> trie::Result<btree_map::IntoIter<H128, FVMValue>> {
+        let mut storage = BTreeMap::new();
+        for (k, v) in committed_storage(db, &self.storage_root)? {
+            let item: U256 = ::rlp::decode(&v);
+            let value = match item.bits() > 128 {
+                true => FVMValue::Long(item.into()),
+                false => FVMValue::Normal(H128::from(&(*H256::from(item))[16..])),
+            };
+            storage.insert(H128::from(&k[..]), value);
+        }
+        for (k, v) in &self.storage_changes.0 {
+            match v.is_zero() {
+                true => storage.remove(k),
+                false => storage.insert(*k, FVMValue::Normal(*v)),
+            };
+        }
+        for (k, v) in &self.storage_changes.1 {
+            match v.is_zero() {
+                true => storage.remove(k),
+                false => storage.insert(*k, FVMValue::Long(*v)),
+            };
+        }
+        Ok(storage.into_iter())
+    }
+
     fn storage_at(&self, db: &HashStore, key: &FVMKey) -> trie::Result<FVMValue> {
+        track_storage(&self.access_tracker, AccessKind::Read, || key.clone().into());
         if let Some(value) = self.cached_storage_at(key) {
             return Ok(value);
         }
@@ -533,7 +1122,7 @@ impl FVMAccount {
                 let value: H128 = item.into();
                 self.storage_cache
                     .0
-                    .borrow_mut()
+                    .lock()
                     .insert(key.clone(), value.clone());
                 Ok(FVMValue::Normal(value))
             },
@@ -542,7 +1131,7 @@ impl FVMAccount {
                 let value: H256 = item.into();
                 self.storage_cache
                     .1
-                    .borrow_mut()
+                    .lock()
                     .insert(key.clone(), value.clone());
                 Ok(FVMValue::Long(value))
             },
@@ -555,7 +1144,7 @@ impl FVMAccount {
                 if let Some(value) = self.storage_changes.0.get(key) {
                     return Some(FVMValue::Normal(value.clone()));
                 }
-                if let Some(value) = self.storage_cache.0.borrow_mut().get_mut(key) {
+                if let Some(value) = self.storage_cache.0.lock().get_mut(key) {
                     return Some(FVMValue::Normal(value.clone()));
                 }
                 None
@@ -564,7 +1153,7 @@ impl FVMAccount {
                 if let Some(value) = self.storage_changes.1.get(key) {
                     return Some(FVMValue::Long(value.clone()));
                 }
-                if let Some(value) = self.storage_cache.1.borrow_mut().get_mut(key) {
+                if let Some(value) = self.storage_cache.1.lock().get_mut(key) {
                     return Some(FVMValue::Long(value.clone()));
                 }
                 None
@@ -572,7 +1161,10 @@ impl FVMAccount {
         }
     }
 
-    fn set_storage(&mut self, key: FVMKey, value: FVMValue) {
+    /// Set a storage value. The change is pending until the storage is committed.
+    pub fn set_storage(&mut self, key: FVMKey, value: FVMValue) {
+        self.filth = Filth::Dirty;
+        track_storage(&self.access_tracker, AccessKind::Write, || key.clone().into());
         match key {
             FVMKey::Normal(key) => {
                 if let FVMValue::Normal(value) = value {
@@ -590,42 +1182,223 @@ impl FVMAccount {
             },
         }
     }
+
+    /// Get the value of storage at `key`, consulting the shared `StateCache` of
+    /// committed values before going to the trie.
+    pub fn storage_at_shared(
+        &self,
+        cache: &StateCache,
+        address: &Address,
+        db: &HashStore,
+        key: &FVMKey,
+    ) -> trie::Result<FVMValue>
+    {
+        track_storage(&self.access_tracker, AccessKind::Read, || key.clone().into());
+        if let Some(value) = self.cached_storage_at(key) {
+            return Ok(value);
+        }
+        let raw_key = match *key {
+            FVMKey::Normal(ref key) | FVMKey::Wide(ref key) => key.to_vec(),
+        };
+        let raw = match cache.storage(address, &self.storage_root, &raw_key) {
+            Some(raw) => raw,
+            None => {
+                let db = SecTrieDB::new(db, &self.storage_root)?;
+                let raw = db.get(&raw_key)?.map_or_else(Vec::new, |v| v.into_vec());
+                cache.insert_storage(*address, self.storage_root, raw_key, raw.clone());
+                raw
+            }
+        };
+
+        match *key {
+            FVMKey::Normal(key) => {
+                let item: U128 = match raw.is_empty() {
+                    true => U128::zero(),
+                    false => ::rlp::decode(&raw),
+                };
+                let value: H128 = item.into();
+                self.storage_cache.0.lock().insert(key, value);
+                Ok(FVMValue::Normal(value))
+            }
+            FVMKey::Wide(key) => {
+                let item: U256 = match raw.is_empty() {
+                    true => U256::zero(),
+                    false => ::rlp::decode(&raw),
+                };
+                let value: H256 = item.into();
+                self.storage_cache.1.lock().insert(key, value);
+                Ok(FVMValue::Long(value))
+            }
+        }
+    }
+
+    /// Commit the `storage_changes` like `commit_storage`, and write the committed
+    /// values through to the shared `StateCache` under the new storage root.
+    pub fn commit_storage_shared(
+        &mut self,
+        cache: &StateCache,
+        address: &Address,
+        trie_factory: &TrieFactory,
+        db: &mut HashStore,
+    ) -> trie::Result<()>
+    {
+        let normal = self.storage_changes.0.iter().map(|(k, v)| {
+            let raw = match v.is_zero() {
+                true => vec![],
+                false => encode(&U128::from(&**v)).into_vec(),
+            };
+            (k.to_vec(), raw)
+        });
+        let wide = self.storage_changes.1.iter().map(|(k, v)| {
+            let raw = match v.is_zero() {
+                true => vec![],
+                false => encode(v).into_vec(),
+            };
+            (k.to_vec(), raw)
+        });
+        // wide changes are applied last in `commit_storage`, so they win here too.
+        let changes: Vec<(Bytes, Bytes)> = normal.chain(wide).collect();
+
+        self.commit_storage(trie_factory, db)?;
+        for (k, v) in changes {
+            cache.insert_storage(*address, self.storage_root, k, v);
+        }
+        Ok(())
+    }
 }
 
 impl AVMAccount {
+    /// Iterate over the effective storage of this account, ordered by key.
+    ///
+    /// Committed entries require the storage trie to be built by a fat `TrieFactory`.
+    /// Pending `storage_changes` take precedence and zero values are left out.
+    pub fn storage_iter(&self, db: &HashStore) -> trie::Result<btree_map::IntoIter<Bytes, Bytes>> {
+        let mut storage = BTreeMap::new();
+        for (k, v) in committed_storage(db, &self.storage_root)? {
+            storage.insert(k, ::rlp::decode(&v));
+        }
+        for (k, v) in &self.storage_changes {
+            match v.iter().all(|b| *b == 0x00_u8) {
+                true => storage.remove(k),
+                false => storage.insert(k.clone(), v.clone()),
+            };
+        }
+        Ok(storage.into_iter())
+    }
+
+    /// Attach a recorder that captures every storage read and write, or detach it with `None`.
+    pub fn set_access_recorder(&mut self, recorder: Option<Arc<AccessRecorder>>) {
+        self.access_recorder = recorder;
+    }
+
+    /// Recorder attached with `set_access_recorder`.
+    pub fn access_recorder(&self) -> Option<&Arc<AccessRecorder>> { self.access_recorder.as_ref() }
+
+    fn record_access(&self, kind: AccessKind, key: &[u8], value: &[u8]) {
+        track_storage(&self.access_tracker, kind, || StorageKey::AVM(key.to_vec()));
+        if let Some(ref recorder) = self.access_recorder {
+            recorder.record(kind, key, value);
+        }
+    }
+
     fn storage_at(&self, db: &HashStore, key: &Bytes) -> trie::Result<Bytes> {
-        println!("get storage: key = {:?}", key);
         if let Some(value) = self.cached_storage_at(key) {
+            trace!(target: "account", "storage_at: key={} value={} (cached)", key.pretty(), value.pretty());
+            self.record_access(AccessKind::Read, key, &value);
             return Ok(value);
         }
         let db = SecTrieDB::new(db, &self.storage_root)?;
 
         let value: Vec<u8> = db.get_with(key, ::rlp::decode)?.unwrap_or_else(|| vec![]);
         self.storage_cache
-            .borrow_mut()
+            .lock()
             .insert(key.clone(), value.clone());
-        println!("get storage value from db: key = {:?}, value = {:?}", key, value);
+        trace!(target: "account", "storage_at: key={} value={} (trie)", key.pretty(), value.pretty());
+        self.record_access(AccessKind::Read, key, &value);
         Ok(value)
     }
 
     fn cached_storage_at(&self, key: &Bytes) -> Option<Bytes> {
-        println!("search storage_changes: {:?}", self.storage_changes);
         if let Some(value) = self.storage_changes.get(key) {
             return Some(value.clone());
         }
 
-        if let Some(value) = self.storage_cache.borrow_mut().get_mut(key) {
+        if let Some(value) = self.storage_cache.lock().get_mut(key) {
             return Some(value.clone());
         }
         None
     }
 
-    fn set_storage(&mut self, key: Bytes, value: Bytes) {
-        println!("pre storage_changes = {:?}", self.storage_changes);
+    /// Set a storage value. The change is pending until the storage is committed.
+    pub fn set_storage(&mut self, key: Bytes, value: Bytes) {
+        self.filth = Filth::Dirty;
+        trace!(target: "account", "set_storage: key={} value={}", key.pretty(), value.pretty());
+        self.record_access(AccessKind::Write, &key, &value);
         self.storage_changes.insert(key, value);
-        let raw_changes: *mut HashMap<Vec<u8>, Vec<u8>> = unsafe {::std::mem::transmute(&self.storage_changes)};
-        println!("storage_changes ptr = {:?}", raw_changes);
-        println!("post storage_changes = {:?}", self.storage_changes);
+    }
+
+    /// Get the value of storage at `key`, consulting the shared `StateCache` of
+    /// committed values before going to the trie.
+    pub fn storage_at_shared(
+        &self,
+        cache: &StateCache,
+        address: &Address,
+        db: &HashStore,
+        key: &Bytes,
+    ) -> trie::Result<Bytes>
+    {
+        if let Some(value) = self.cached_storage_at(key) {
+            trace!(target: "account", "storage_at_shared: key={} value={} (cached)", key.pretty(), value.pretty());
+            self.record_access(AccessKind::Read, key, &value);
+            return Ok(value);
+        }
+        let raw = match cache.storage(address, &self.storage_root, key) {
+            Some(raw) => raw,
+            None => {
+                let db = SecTrieDB::new(db, &self.storage_root)?;
+                let raw = db.get(key)?.map_or_else(Vec::new, |v| v.into_vec());
+                cache.insert_storage(*address, self.storage_root, key.clone(), raw.clone());
+                raw
+            }
+        };
+
+        let value: Bytes = match raw.is_empty() {
+            true => vec![],
+            false => ::rlp::decode(&raw),
+        };
+        self.storage_cache.lock().insert(key.clone(), value.clone());
+        trace!(target: "account", "storage_at_shared: key={} value={}", key.pretty(), value.pretty());
+        self.record_access(AccessKind::Read, key, &value);
+        Ok(value)
+    }
+
+    /// Commit the `storage_changes` like `commit_storage`, and write the committed
+    /// values through to the shared `StateCache` under the new storage root.
+    pub fn commit_storage_shared(
+        &mut self,
+        cache: &StateCache,
+        address: &Address,
+        trie_factory: &TrieFactory,
+        db: &mut HashStore,
+    ) -> trie::Result<()>
+    {
+        let changes: Vec<(Bytes, Bytes)> = self
+            .storage_changes
+            .iter()
+            .map(|(k, v)| {
+                let raw = match v.iter().all(|b| *b == 0x00_u8) {
+                    true => vec![],
+                    false => encode(v).into_vec(),
+                };
+                (k.clone(), raw)
+            })
+            .collect();
+
+        self.commit_storage(trie_factory, db)?;
+        for (k, v) in changes {
+            cache.insert_storage(*address, self.storage_root, k, v);
+        }
+        Ok(())
     }
 }
 
@@ -634,6 +1407,9 @@ mod tests {
     use super::*;
     use kvdb::MemoryDB;
     use account_db::*;
+    use traits::transfer;
+    use trie::TrieSpec;
+    use access::ReadWriteSet;
 
     #[test]
     fn storage_at() {
@@ -664,7 +1440,455 @@ mod tests {
             FVMValue::Normal(H128::default())
         );
     }
-}
 
-unsafe impl Sync for FVMAccount {}
-unsafe impl Sync for AVMAccount {}
\ No newline at end of file
+    #[test]
+    fn concurrent_storage_at() {
+        let mut db = MemoryDB::new();
+        let rlp = {
+            let mut db = AccountDBMut::new(&mut db, &Address::new());
+            let mut a = AVMAccount::new_contract(0.into(), 0.into());
+            for i in 0..16u8 {
+                a.set_storage(vec![i], vec![i + 1]);
+            }
+            a.commit_storage(&Default::default(), &mut db).unwrap();
+            a.rlp()
+        };
+
+        let db = Arc::new(db);
+        let a = Arc::new(AVMAccount::from_rlp(&rlp));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                let a = a.clone();
+                ::std::thread::spawn(move || {
+                    let db = AccountDB::from_hash(&*db, blake2b(Address::new()));
+                    for i in 0..16u8 {
+                        assert_eq!(a.storage_at(&db, &vec![i]).unwrap(), vec![i + 1]);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(a.storage_cache.lock().len(), 16);
+    }
+
+    #[test]
+    fn shared_state_cache() {
+        let cache = StateCache::new(16, 4);
+        let address = Address::from(0x10);
+        let mut db = MemoryDB::new();
+
+        let mut a = AVMAccount::new_contract(0.into(), 0.into());
+        a.set_storage(vec![0x01], vec![0x11]);
+        a.set_storage(vec![0x02], vec![0x22]);
+        a.init_code(vec![0x60, 0x00]);
+        {
+            let mut db = AccountDBMut::new(&mut db, &address);
+            a.commit_storage_shared(&cache, &address, &Default::default(), &mut db).unwrap();
+            a.commit_code(&mut db);
+        }
+        let old_rlp = a.rlp();
+        let old_db = db.clone();
+        a.set_storage(vec![0x01], vec![0x12]);
+        {
+            let mut db = AccountDBMut::new(&mut db, &address);
+            a.commit_storage_shared(&cache, &address, &Default::default(), &mut db).unwrap();
+        }
+        let new_rlp = a.rlp();
+        let db = AccountDB::from_hash(&db, blake2b(address));
+
+        // next block: a fresh account instance hits the written-through value.
+        let mut a = AVMAccount::from_rlp(&new_rlp);
+        assert_eq!(a.storage_at_shared(&cache, &address, &db, &vec![0x01]).unwrap(), vec![0x12]);
+        assert_eq!(cache.stats().storage_hits, 1);
+
+        // key 0x02 was cached under the old root only.
+        assert_eq!(a.storage_at_shared(&cache, &address, &db, &vec![0x02]).unwrap(), vec![0x22]);
+        assert_eq!(cache.stats().storage_misses, 1);
+
+        // a state on the old root does not see the newer value.
+        let old = AVMAccount::from_rlp(&old_rlp);
+        let old_db = AccountDB::from_hash(&old_db, blake2b(address));
+        assert_eq!(old.storage_at_shared(&cache, &address, &old_db, &vec![0x01]).unwrap(), vec![0x11]);
+        assert_eq!(cache.stats().storage_misses, 2);
+
+        assert_eq!(*a.cache_code_shared(&cache, &db).unwrap(), vec![0x60, 0x00]);
+        let mut b = AVMAccount::from_rlp(&new_rlp);
+        assert_eq!(*b.cache_code_shared(&cache, &db).unwrap(), vec![0x60, 0x00]);
+        assert_eq!(cache.stats().code_misses, 1);
+        assert_eq!(cache.stats().code_hits, 1);
+    }
+
+    #[test]
+    fn storage_iter() {
+        let mut db = MemoryDB::new();
+        let mut db = AccountDBMut::new(&mut db, &Address::new());
+        let factory = TrieFactory::new(TrieSpec::Fat);
+
+        let mut a = AVMAccount::new_contract(0.into(), 0.into());
+        a.set_storage(vec![0x01], vec![0x11]);
+        a.set_storage(vec![0x02], vec![0x22]);
+        a.set_storage(vec![0x03], vec![0x33]);
+        a.commit_storage(&factory, &mut db).unwrap();
+        a.set_storage(vec![0x02], vec![0x00]);
+        a.set_storage(vec![0x03], vec![0x34]);
+        a.set_storage(vec![0x04], vec![0x44]);
+
+        assert_eq!(
+            a.storage_iter(&db).unwrap().collect::<Vec<_>>(),
+            vec![
+                (vec![0x01], vec![0x11]),
+                (vec![0x03], vec![0x34]),
+                (vec![0x04], vec![0x44]),
+            ]
+        );
+
+        let mut f = FVMAccount::new_contract(0.into(), 0.into());
+        f.set_storage(FVMKey::Normal(0x01u64.into()), FVMValue::Normal(0x11u64.into()));
+        f.set_storage(FVMKey::Wide(0x02u64.into()), FVMValue::Long(H256::from(U256::max_value())));
+        f.commit_storage(&factory, &mut db).unwrap();
+        f.set_storage(FVMKey::Normal(0x01u64.into()), FVMValue::Normal(H128::zero()));
+        f.set_storage(FVMKey::Normal(0x03u64.into()), FVMValue::Normal(0x33u64.into()));
+
+        assert_eq!(
+            f.storage_iter(&db).unwrap().collect::<Vec<_>>(),
+            vec![
+                (0x02u64.into(), FVMValue::Long(H256::from(U256::max_value()))),
+                (0x03u64.into(), FVMValue::Normal(0x33u64.into())),
+            ]
+        );
+
+        let mut s = AVMAccount::new_contract(0.into(), 0.into());
+        s.set_storage(vec![0x05], vec![0x55]);
+        s.commit_storage(&Default::default(), &mut db).unwrap();
+        assert!(s.storage_iter(&db).is_err());
+    }
+
+    #[test]
+    fn account_type_rlp() {
+        let mut a = AVMAccount::new_contract(69.into(), 0.into());
+        a.init_code(vec![0x55, 0x44]);
+        let rlp = a.rlp();
+        assert_eq!(Rlp::new(&rlp).item_count(), 5);
+
+        match VMAccount::from_rlp(&rlp) {
+            VMAccount::AVM(b) => {
+                assert_eq!(b.account_type(), AccType::AVM);
+                assert_eq!(b.code_hash(), a.code_hash());
+            }
+            _ => panic!("expected AVM account"),
+        }
+
+        let b = VMAccount::from_rlp(&FVMAccount::new_basic(1.into(), 0.into()).rlp());
+        assert_eq!(b.account_type(), AccType::Normal);
+        assert_eq!(*b.balance(), 1.into());
+    }
+
+    #[test]
+    fn avm_code_and_objectgraph() {
+        let mut db = MemoryDB::new();
+        let mut db = AccountDBMut::new(&mut db, &Address::new());
+        let mut a = AVMAccount::new_contract(0.into(), 0.into());
+        a.init_code(vec![0x50, 0x4b]);
+        a.init_transformed_code(vec![0x50, 0x4b, 0x03]);
+        a.set_objectgraph(vec![0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(a.transformed_code_filth, Filth::Dirty);
+        a.commit_code(&mut db);
+        assert_eq!(a.transformed_code_filth, Filth::Clean);
+        assert_eq!(a.objectgraph_filth, Filth::Clean);
+
+        let rlp = a.rlp();
+        assert_eq!(Rlp::new(&rlp).item_count(), 7);
+        let mut b = AVMAccount::from_rlp(&rlp);
+        assert_eq!(b.transformed_code_hash(), blake2b(&[0x50u8, 0x4b, 0x03][..]));
+        assert_eq!(b.objectgraph(), None);
+        assert_eq!(*b.cache_transformed_code(&db).unwrap(), vec![0x50, 0x4b, 0x03]);
+        assert_eq!(*b.cache_objectgraph(&db).unwrap(), vec![0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(b.objectgraph_size(), Some(4));
+        assert_eq!(*b.cache_code(&db).unwrap(), vec![0x50, 0x4b]);
+
+        // a new object graph changes the account hash.
+        b.set_objectgraph(vec![0x05]);
+        assert!(b.rlp() != rlp);
+        match VMAccount::from_rlp(&b.rlp()) {
+            VMAccount::AVM(c) => assert_eq!(c.objectgraph_hash(), blake2b(&[0x05u8][..])),
+            _ => panic!("expected AVM account"),
+        }
+    }
+
+    #[test]
+    fn avm_overwrite_with() {
+        let mut db = MemoryDB::new();
+        let mut db = AccountDBMut::new(&mut db, &Address::new());
+        let mut a = AVMAccount::new_contract(0.into(), 0.into());
+        a.init_code(vec![0x50, 0x4b]);
+        a.commit_code(&mut db);
+        let mut cached = AVMAccount::from_rlp(&a.rlp());
+
+        let mut checkpoint = cached.clone_dirty();
+        checkpoint.init_transformed_code(vec![0x50, 0x4b, 0x03]);
+        checkpoint.set_objectgraph(vec![0x01, 0x02]);
+        cached.overwrite_with(checkpoint);
+
+        assert_eq!(cached.transformed_code_hash(), blake2b(&[0x50u8, 0x4b, 0x03][..]));
+        assert_eq!(*cached.transformed_code().unwrap(), vec![0x50, 0x4b, 0x03]);
+        assert_eq!(cached.transformed_code_size(), Some(3));
+        assert_eq!(cached.objectgraph_hash(), blake2b(&[0x01u8, 0x02][..]));
+        assert_eq!(*cached.objectgraph().unwrap(), vec![0x01, 0x02]);
+        assert_eq!(cached.objectgraph_filth, Filth::Dirty);
+
+        cached.commit_code(&mut db);
+        let mut b = AVMAccount::from_rlp(&cached.rlp());
+        assert_eq!(*b.cache_transformed_code(&db).unwrap(), vec![0x50, 0x4b, 0x03]);
+        assert_eq!(*b.cache_objectgraph(&db).unwrap(), vec![0x01, 0x02]);
+    }
+
+    #[test]
+    fn avm_access_recorder() {
+        let mut db = MemoryDB::new();
+        let mut db = AccountDBMut::new(&mut db, &Address::new());
+        let mut a = AVMAccount::new_contract(0.into(), 0.into());
+        a.set_storage(vec![0x01], vec![0x11]);
+        a.commit_storage(&Default::default(), &mut db).unwrap();
+
+        let recorder = Arc::new(AccessRecorder::new());
+        let mut a = AVMAccount::from_rlp(&a.rlp());
+        a.set_access_recorder(Some(recorder.clone()));
+        assert_eq!(a.storage_at(&db, &vec![0x01]).unwrap(), vec![0x11]);
+        a.set_storage(vec![0x02], vec![0x22]);
+        assert_eq!(a.storage_at(&db, &vec![0x02]).unwrap(), vec![0x22]);
+        assert_eq!(a.storage_at(&db, &vec![0x03]).unwrap(), Vec::<u8>::new());
+
+        let kinds: Vec<_> = recorder.accesses().iter().map(|access| access.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![AccessKind::Read, AccessKind::Write, AccessKind::Read, AccessKind::Read]
+        );
+        assert_eq!(recorder.accesses()[1].value, vec![0x22]);
+        assert_eq!(
+            recorder.access_list().into_iter().collect::<Vec<_>>(),
+            vec![vec![0x01], vec![0x02], vec![0x03]]
+        );
+        assert_eq!(recorder.drain().len(), 4);
+
+        a.set_access_recorder(None);
+        a.storage_at(&db, &vec![0x01]).unwrap();
+        assert!(recorder.accesses().is_empty());
+    }
+
+    #[test]
+    fn read_write_tracking() {
+        let tracker = Arc::new(ReadWriteTracker::new());
+        let mut db = MemoryDB::new();
+        let db = AccountDBMut::new(&mut db, &Address::new());
+        let (x, y, z) = (Address::from(1), Address::from(2), Address::from(3));
+
+        let mut a = FVMAccount::new_basic(10.into(), 0.into());
+        a.track_access(x, tracker.clone());
+        let mut b = VMAccount::from(AVMAccount::new_contract(0.into(), 0.into()));
+        b.track_access(y, tracker.clone());
+        transfer(&mut a, &mut b, &5.into()).unwrap();
+        a.storage_at(&db, &FVMKey::Normal(0x01u64.into())).unwrap();
+        a.set_storage(FVMKey::Wide(0x02u64.into()), FVMValue::Long(0x03u64.into()));
+        if let VMAccount::AVM(ref mut b) = b {
+            b.set_storage(vec![0x01], vec![0x02]);
+        }
+        let first = tracker.take();
+        assert_eq!(first.reads.len(), 2);
+        assert_eq!(first.writes.len(), 2);
+        assert!(first.storage_reads.contains(&(x, StorageKey::FVM(0x01u64.into()))));
+        assert!(first.storage_writes.contains(&(x, StorageKey::FVM(0x02u64.into()))));
+        assert!(first.storage_writes.contains(&(y, StorageKey::AVM(vec![0x01]))));
+
+        // writing the same slot key of another account does not conflict.
+        let mut c = AVMAccount::new_contract(0.into(), 0.into());
+        c.track_access(z, tracker.clone());
+        c.set_storage(vec![0x01], vec![0x05]);
+        let second = tracker.take();
+        assert!(!first.conflicts_with(&second));
+
+        // reading the balance of an account written by the first set does.
+        let mut third = ReadWriteSet::default();
+        third.reads.insert(y);
+        assert!(first.conflicts_with(&third));
+        assert!(third.conflicts_with(&first));
+
+        let mut fourth = ReadWriteSet::default();
+        fourth.storage_reads.insert((x, StorageKey::FVM(0x02u64.into())));
+        assert!(fourth.conflicts_with(&first));
+        assert!(!fourth.conflicts_with(&second));
+
+        // a normal read of the slot written as a wide value conflicts as well.
+        let mut d = FVMAccount::new_basic(0.into(), 0.into());
+        d.track_access(x, tracker.clone());
+        d.storage_at(&db, &FVMKey::Normal(0x02u64.into())).unwrap();
+        let fifth = tracker.take();
+        assert!(fifth.storage_reads.contains(&(x, StorageKey::from(FVMKey::Wide(0x02u64.into())))));
+        assert!(fifth.conflicts_with(&first));
+
+        // a rejected withdrawal reads the account without writing it.
+        let mut e = FVMAccount::new_basic(1.into(), 0.into());
+        e.track_access(z, tracker.clone());
+        assert!(e.checked_sub_balance(&2.into()).is_err());
+        assert_eq!(*e.balance(), 1.into());
+        let sixth = tracker.take();
+        assert!(sixth.writes.is_empty());
+        assert!(sixth.reads.contains(&z));
+    }
+
+    #[test]
+    fn dirty_tracking() {
+        let mut db = MemoryDB::new();
+        let mut db = AccountDBMut::new(&mut db, &Address::new());
+
+        let mut a = FVMAccount::new_contract(0.into(), 0.into());
+        assert!(a.is_dirty());
+        let rlp = a.commit_rlp().unwrap();
+        assert!(!a.is_dirty());
+        assert_eq!(a.commit_rlp(), None);
+
+        let mut a = FVMAccount::from_rlp(&rlp);
+        assert!(!a.is_dirty());
+        a.storage_at(&db, &FVMKey::Normal(0x01u64.into())).unwrap();
+        a.cache_code(&db);
+        assert_eq!(a.commit_rlp(), None);
+
+        a.add_balance(&1.into());
+        assert!(a.is_dirty());
+        assert!(a.commit_rlp().is_some());
+
+        a.set_storage(FVMKey::Normal(0x01u64.into()), FVMValue::Normal(0x02u64.into()));
+        a.commit_storage(&Default::default(), &mut db).unwrap();
+        let rlp = a.commit_rlp().unwrap();
+        assert_eq!(FVMAccount::from_rlp(&rlp).storage_root, a.storage_root);
+
+        // failed checked arithmetic leaves the account clean.
+        assert!(a.checked_sub_balance(&2.into()).is_err());
+        assert!(!a.is_dirty());
+
+        let mut b = VMAccount::from(AVMAccount::from_rlp(&AVMAccount::new_contract(0.into(), 0.into()).rlp()));
+        assert!(!b.is_dirty());
+        b.init_code(vec![0x01]);
+        assert!(b.is_dirty());
+        b.commit_code(&mut db);
+        assert!(b.commit_rlp().is_some());
+        assert!(!b.is_dirty());
+    }
+
+    #[test]
+    fn overwrite_with_dirty() {
+        let rlp = FVMAccount::new_basic(10.into(), 0.into()).rlp();
+        let mut cached = FVMAccount::from_rlp(&rlp);
+        let mut checkpoint = cached.clone_dirty();
+        checkpoint.inc_nonce();
+        checkpoint.add_balance(&1.into());
+        cached.overwrite_with(checkpoint);
+        assert!(cached.is_dirty());
+        let committed = FVMAccount::from_rlp(&cached.commit_rlp().unwrap());
+        assert_eq!((*committed.balance(), *committed.nonce()), (11.into(), 1.into()));
+
+        let mut cached = AVMAccount::from_rlp(&AVMAccount::new_contract(10.into(), 0.into()).rlp());
+        let mut checkpoint = cached.clone_dirty();
+        checkpoint.sub_balance(&1.into());
+        cached.overwrite_with(checkpoint);
+        assert!(cached.commit_rlp().is_some());
+    }
+
+    #[test]
+    fn checked_balance() {
+        let mut a = FVMAccount::new_basic(10.into(), U256::max_value());
+        let mut b = AVMAccount::new_contract(U256::max_value() - 5.into(), 0.into());
+
+        assert_eq!(a.checked_inc_nonce(), Err(Error::NonceOverflow));
+        assert_eq!(*a.nonce(), U256::max_value());
+        assert_eq!(
+            a.checked_sub_balance(&11.into()),
+            Err(Error::BalanceUnderflow {
+                balance: 10.into(),
+                amount: 11.into(),
+            })
+        );
+        assert_eq!(
+            b.checked_add_balance(&6.into()),
+            Err(Error::BalanceOverflow {
+                balance: U256::max_value() - 5.into(),
+                amount: 6.into(),
+            })
+        );
+
+        assert!(transfer(&mut a, &mut b, &6.into()).is_err());
+        assert_eq!(*a.balance(), 10.into());
+        assert_eq!(*b.balance(), U256::max_value() - 5.into());
+
+        transfer(&mut a, &mut b, &5.into()).unwrap();
+        assert_eq!(*a.balance(), 5.into());
+        assert_eq!(*b.balance(), U256::max_value());
+    }
+
+    #[test]
+    fn legacy_account_rlp() {
+        let mut stream = RlpStream::new_list(4);
+        stream.append(&U256::from(1));
+        stream.append(&U256::from(2));
+        stream.append(&BLAKE2B_NULL_RLP);
+        stream.append(&blake2b([0x60u8, 0x00]));
+        let rlp = stream.out();
+
+        let a = VMAccount::from_rlp(&rlp);
+        assert_eq!(a.account_type(), AccType::FVM);
+        assert_eq!(*a.nonce(), 1.into());
+        assert_eq!(*a.balance(), 2.into());
+        assert_eq!(a.rlp(), rlp);
+
+        let mut a = FVMAccount::from_rlp(&rlp);
+        a.inc_nonce();
+        a.add_balance(&1.into());
+        assert_eq!(Rlp::new(&a.commit_rlp().unwrap()).item_count(), 4);
+
+        let rlp = BasicAccount {
+            nonce: 0.into(),
+            balance: 0.into(),
+            storage_root: BLAKE2B_NULL_RLP,
+            code_hash: BLAKE2B_EMPTY,
+        }.rlp_bytes().into_vec();
+        let a = FVMAccount::from_rlp(&rlp);
+        assert_eq!(a.account_type(), AccType::Normal);
+        assert_eq!(a.rlp(), rlp);
+    }
+
+    #[test]
+    fn invalid_account_rlp() {
+        let account = |items: usize, marker: u8| {
+            let mut stream = RlpStream::new_list(items);
+            stream.append(&U256::from(1));
+            stream.append(&U256::from(2));
+            stream.append(&BLAKE2B_NULL_RLP);
+            stream.append(&BLAKE2B_EMPTY);
+            for _ in 4..items {
+                stream.append(&marker);
+            }
+            stream.out()
+        };
+
+        assert!(decode_account(&account(5, AccType::AVM as u8)).is_ok());
+        assert_eq!(
+            decode_account(&account(5, 0x03)),
+            Err(DecoderError::Custom("invalid account type marker"))
+        );
+        assert_eq!(decode_account(&account(6, 0)), Err(DecoderError::RlpIncorrectListLen));
+        assert_eq!(decode_account(&account(8, 0)), Err(DecoderError::RlpIncorrectListLen));
+        // the AVM hashes are decoded along with the rest.
+        assert!(decode_account(&account(7, AccType::AVM as u8)).is_err());
+        let avm = AVMAccount::new_contract(1.into(), 2.into()).rlp();
+        let mut stream = RlpStream::new_list(7);
+        for i in 0..5 {
+            stream.append_raw(Rlp::new(&avm).at(i).as_raw(), 1);
+        }
+        stream.append(&BLAKE2B_EMPTY);
+        stream.append(&BLAKE2B_NULL_RLP);
+        let decoded = decode_account(&stream.out()).unwrap();
+        assert_eq!(decoded.avm_hashes, Some((BLAKE2B_EMPTY, BLAKE2B_NULL_RLP)));
+    }
+}
@@ -0,0 +1,38 @@
+use std::fmt;
+use std::hash::Hash;
+
+use lru_cache::LruCache;
+use parking_lot::{Mutex, MutexGuard};
+
+/// LRU cache of storage values that can be shared between threads.
+/// A cache hit updates the recency order, so readers take the lock exclusively.
+pub struct StorageCache<K: Eq + Hash, V> {
+    cache: Mutex<LruCache<K, V>>,
+}
+
+impl<K: Eq + Hash, V> StorageCache<K, V> {
+    /// Create an empty cache holding at most `capacity` items.
+    pub fn new(capacity: usize) -> Self {
+        StorageCache {
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Lock the cache for access.
+    pub fn lock(&self) -> MutexGuard<'_, LruCache<K, V>> { self.cache.lock() }
+
+    /// Consume the cache and return the underlying LRU cache.
+    pub fn into_inner(self) -> LruCache<K, V> { self.cache.into_inner() }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Clone for StorageCache<K, V> {
+    fn clone(&self) -> Self {
+        StorageCache {
+            cache: Mutex::new(self.cache.lock().clone()),
+        }
+    }
+}
+
+impl<K: Eq + Hash + fmt::Debug, V: fmt::Debug> fmt::Debug for StorageCache<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { self.cache.lock().fmt(f) }
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+
+use aion_types::{H256, U256};
+use rlp::DecoderError;
+use trie::TrieError;
+
+/// Errors of account level operations.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// Balance is lower than the amount to be subtracted.
+    BalanceUnderflow { balance: U256, amount: U256 },
+    /// Balance would exceed the maximum value of `U256`.
+    BalanceOverflow { balance: U256, amount: U256 },
+    /// Nonce would exceed the maximum value of `U256`.
+    NonceOverflow,
+    /// Trie lookup failed.
+    Trie(TrieError),
+    /// Malformed RLP.
+    Decoder(DecoderError),
+    /// Snapshot chunk could not be decompressed.
+    InvalidChunk,
+    /// Code referenced by a snapshot was never restored.
+    MissingCode(H256),
+    /// Restored state does not have the expected root.
+    StateRootMismatch { expected: H256, found: H256 },
+    /// Plain account dbs do not scope keys by account and can not be migrated.
+    PlainAccountDbMigration,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BalanceUnderflow {
+                ref balance,
+                ref amount,
+            } => write!(f, "insufficient balance: {} < {}", balance, amount),
+            Error::BalanceOverflow {
+                ref balance,
+                ref amount,
+            } => write!(f, "balance overflow: {} + {}", balance, amount),
+            Error::NonceOverflow => write!(f, "nonce overflow"),
+            Error::Trie(ref err) => write!(f, "trie error: {}", err),
+            Error::Decoder(ref err) => write!(f, "rlp error: {}", err),
+            Error::InvalidChunk => write!(f, "invalid snapshot chunk"),
+            Error::MissingCode(ref hash) => write!(f, "missing code: {}", hash),
+            Error::StateRootMismatch {
+                ref expected,
+                ref found,
+            } => write!(f, "state root mismatch: expected {}, found {}", expected, found),
+            Error::PlainAccountDbMigration => write!(f, "plain account db can not be migrated"),
+        }
+    }
+}
+
+impl ::std::error::Error for Error {
+    fn description(&self) -> &str { "account error" }
+}
+
+impl From<TrieError> for Error {
+    fn from(err: TrieError) -> Self { Error::Trie(err) }
+}
+
+impl From<Box<TrieError>> for Error {
+    fn from(err: Box<TrieError>) -> Self { Error::Trie(*err) }
+}
+
+impl From<DecoderError> for Error {
+    fn from(err: DecoderError) -> Self { Error::Decoder(err) }
+}
+
+pub type Result<T> = ::std::result::Result<T, Error>;
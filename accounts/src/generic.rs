@@ -1,9 +1,12 @@
-use std::cell::{Cell};
 use std::sync::Arc;
 
-use aion_types::{H256, U256};
+use parking_lot::RwLock;
+
+use aion_types::{Address, H256, U256};
 use bytes::{Bytes};
 
+use access::{AccessRecorder, ReadWriteTracker};
+
 /// Basic account type.
 #[derive(Debug, Clone, PartialEq, Eq, RlpEncodable, RlpDecodable)]
 pub struct BasicAccount {
@@ -20,7 +23,7 @@ pub struct BasicAccount {
 /// Single account in the system.
 /// Keeps track of changes to the code and storage.
 /// The changes are applied in `commit_storage` and `commit_code`
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Account<T, U> {
     // Balance of the account.
     pub balance: U256,
@@ -44,10 +47,87 @@ pub struct Account<T, U> {
     // Account code new or has been modified.
     pub code_filth: Filth,
     // Cached address hash.
-    pub address_hash: Cell<Option<H256>>,
+    pub address_hash: RwLock<Option<H256>>,
     // empty_flag: for Aion Java Kernel Only
     pub empty_but_commit: bool,
     // account type: 0x00 = normal; 0x01 = EVM; 0x02 = AVM
+    pub account_type: AccType,
+    // AVM only: hash of the transformed code deployed by the AVM.
+    // `code_hash` keeps the original code as submitted.
+    pub transformed_code_hash: H256,
+    // Size of the transformed code.
+    pub transformed_code_size: Option<usize>,
+    // Transformed code cache of the account.
+    pub transformed_code_cache: Arc<Bytes>,
+    // Transformed code new or has been modified.
+    pub transformed_code_filth: Filth,
+    // AVM only: hash of the serialized object graph.
+    pub objectgraph_hash: H256,
+    // Size of the object graph.
+    pub objectgraph_size: Option<usize>,
+    // Object graph cache of the account.
+    pub objectgraph_cache: Arc<Bytes>,
+    // Object graph new or has been modified.
+    pub objectgraph_filth: Filth,
+    // AVM only: optional recorder of storage reads and writes.
+    pub access_recorder: Option<Arc<AccessRecorder>>,
+    // Optional read/write set tracking, with the address of this account.
+    pub access_tracker: Option<(Address, Arc<ReadWriteTracker>)>,
+    // Balance, nonce, storage or code changed since the account was loaded or last committed.
+    pub filth: Filth,
+}
+
+impl<T: Clone, U: Clone> Clone for Account<T, U> {
+    fn clone(&self) -> Self {
+        Account {
+            balance: self.balance,
+            nonce: self.nonce,
+            storage_root: self.storage_root,
+            storage_cache: self.storage_cache.clone(),
+            storage_changes: self.storage_changes.clone(),
+            code_hash: self.code_hash,
+            code_size: self.code_size,
+            code_cache: self.code_cache.clone(),
+            code_filth: self.code_filth,
+            address_hash: RwLock::new(*self.address_hash.read()),
+            empty_but_commit: self.empty_but_commit,
+            account_type: self.account_type,
+            transformed_code_hash: self.transformed_code_hash,
+            transformed_code_size: self.transformed_code_size,
+            transformed_code_cache: self.transformed_code_cache.clone(),
+            transformed_code_filth: self.transformed_code_filth,
+            objectgraph_hash: self.objectgraph_hash,
+            objectgraph_size: self.objectgraph_size,
+            objectgraph_cache: self.objectgraph_cache.clone(),
+            objectgraph_filth: self.objectgraph_filth,
+            access_recorder: self.access_recorder.clone(),
+            access_tracker: self.access_tracker.clone(),
+            filth: self.filth,
+        }
+    }
+}
+
+/// Account type marker, persisted in the RLP of AVM accounts.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AccType {
+    /// Basic account without code.
+    Normal = 0x00,
+    /// FastVM (EVM compatible) contract account.
+    FVM = 0x01,
+    /// AVM contract account.
+    AVM = 0x02,
+}
+
+impl AccType {
+    /// Convert the persisted marker byte back to an account type.
+    pub fn from_u8(value: u8) -> Option<AccType> {
+        match value {
+            0x00 => Some(AccType::Normal),
+            0x01 => Some(AccType::FVM),
+            0x02 => Some(AccType::AVM),
+            _ => None,
+        }
+    }
 }
 
 /// Boolean type for clean/dirty status.
@@ -13,11 +13,28 @@ extern crate rlp_derive;
 extern crate db as kvdb;
 extern crate patricia_trie as trie;
 extern crate parking_lot;
+extern crate parity_snappy;
 
 //mod accounts;
 mod generic;
+mod access;
 mod accounts;
 mod traits;
 mod account_db;
+mod cache;
+mod state_cache;
+mod error;
+mod snapshot;
+mod proof;
+mod pruning;
 
-pub use accounts::{FVMAccount, AVMAccount};
\ No newline at end of file
+pub use accounts::{BasicAccount, FVMAccount, AVMAccount, VMAccount, FVMKey, FVMValue};
+pub use generic::AccType;
+pub use access::{AccessKind, AccessRecorder, StorageAccess, StorageKey, ReadWriteSet, ReadWriteTracker};
+pub use traits::{CommonAccount, transfer};
+pub use pruning::{PruningPolicy, commit_accounts};
+pub use error::Error;
+pub use state_cache::{StateCache, CacheStats};
+pub use account_db::Factory as AccountDBFactory;
+pub use snapshot::{chunk_state, StateRebuilder, PREFERRED_CHUNK_SIZE};
+pub use proof::{prove_account, prove_storage, verify_account_proof, verify_storage_proof, StorageProof};
\ No newline at end of file
@@ -0,0 +1,202 @@
+//! Merkle proofs of accounts and storage values.
+//!
+//! A proof is the list of trie nodes visited while looking up a key, starting at the root.
+//! Verification loads the nodes into a throwaway in-memory store and repeats the lookup,
+//! so it needs nothing but the trusted root.
+
+use aion_types::{Address, H256};
+use blake2b::blake2b;
+use bytes::Bytes;
+use kvdb::{HashStore, MemoryDB};
+use trie::{Recorder, SecTrieDB, Trie};
+
+use account_db::Factory;
+use accounts::{decode_account, BasicAccount};
+use error::Result;
+
+/// Proof of a storage value: the proof of the owning account followed by the
+/// proof of the value in the account's storage trie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageProof {
+    /// State trie nodes leading to the account.
+    pub account: Vec<Bytes>,
+    /// Storage trie nodes leading to the value.
+    pub storage: Vec<Bytes>,
+}
+
+fn record(db: &HashStore, root: &H256, key: &[u8]) -> Result<(Vec<Bytes>, Option<Bytes>)> {
+    let mut recorder = Recorder::new();
+    let value = SecTrieDB::new(db, root)?.get_with(key, &mut recorder)?;
+    let nodes = recorder.drain().into_iter().map(|record| record.data).collect();
+    Ok((nodes, value.map(|value| value.into_vec())))
+}
+
+fn lookup(proof: &[Bytes], root: &H256, key: &[u8]) -> Result<Option<Bytes>> {
+    let mut db = MemoryDB::new();
+    for node in proof {
+        db.insert(node);
+    }
+    let value = SecTrieDB::new(&db, root)?.get(key)?;
+    Ok(value.map(|value| value.into_vec()))
+}
+
+/// Nodes proving the presence or absence of `address` in the state at `state_root`.
+pub fn prove_account(db: &HashStore, state_root: &H256, address: &Address) -> Result<Vec<Bytes>> {
+    record(db, state_root, address).map(|(nodes, _)| nodes)
+}
+
+/// Nodes proving the value of storage `key` of `address` in the state at `state_root`.
+/// The storage proof is empty when the account does not exist.
+pub fn prove_storage(
+    db: &HashStore,
+    state_root: &H256,
+    factory: &Factory,
+    address: &Address,
+    key: &[u8],
+) -> Result<StorageProof>
+{
+    let (account, rlp) = record(db, state_root, address)?;
+    let storage = match rlp {
+        Some(rlp) => {
+            let basic = decode_account(&rlp)?.basic;
+            let account_db = factory.readonly(db, blake2b(address));
+            record(&*account_db, &basic.storage_root, key)?.0
+        }
+        None => Vec::new(),
+    };
+    Ok(StorageProof {
+        account: account,
+        storage: storage,
+    })
+}
+
+/// Check an account proof against a trusted `state_root`.
+/// Returns `None` if the proof shows that the account does not exist,
+/// and an error if the proof is incomplete or does not match the root.
+pub fn verify_account_proof(state_root: &H256, address: &Address, proof: &[Bytes]) -> Result<Option<BasicAccount>> {
+    let rlp = lookup(proof, state_root, address)?;
+    match rlp {
+        Some(rlp) => Ok(Some(decode_account(&rlp)?.basic)),
+        None => Ok(None),
+    }
+}
+
+/// Check a storage proof against a trusted `state_root`.
+/// Returns the RLP encoded value, or `None` if the account or the key does not exist.
+pub fn verify_storage_proof(
+    state_root: &H256,
+    address: &Address,
+    key: &[u8],
+    proof: &StorageProof,
+) -> Result<Option<Bytes>>
+{
+    match verify_account_proof(state_root, address, &proof.account)? {
+        Some(basic) => lookup(&proof.storage, &basic.storage_root, key),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aion_types::{H128, U128, U256};
+    use account_db::AccountDBMut;
+    use accounts::{AVMAccount, FVMAccount, FVMKey, FVMValue};
+    use error::Error;
+    use state_cache::StateCache;
+    use traits::CommonAccount;
+    use trie::{SecTrieDBMut, TrieError, TrieMut};
+
+    fn build_state(db: &mut MemoryDB) -> H256 {
+        let cache = StateCache::default();
+        let mut accounts = Vec::new();
+        for i in 1..32u64 {
+            let address = Address::from(i);
+            let mut a = FVMAccount::new_contract((i * 100).into(), i.into());
+            a.set_storage(FVMKey::Normal(i.into()), FVMValue::Normal((i + 1).into()));
+            {
+                let mut db = AccountDBMut::new(db, &address);
+                a.commit_storage_shared(&cache, &address, &Default::default(), &mut db).unwrap();
+            }
+            accounts.push((address, a.rlp()));
+        }
+        let address = Address::from(0x100);
+        let mut a = AVMAccount::new_contract(7.into(), 0.into());
+        a.set_storage(vec![0x01, 0x02], vec![0x03]);
+        {
+            let mut db = AccountDBMut::new(db, &address);
+            a.commit_storage_shared(&cache, &address, &Default::default(), &mut db).unwrap();
+        }
+        accounts.push((address, a.rlp()));
+
+        let mut root = H256::default();
+        {
+            let mut trie = SecTrieDBMut::new(db, &mut root);
+            for (address, rlp) in accounts {
+                trie.insert(&address, &rlp).unwrap();
+            }
+        }
+        root
+    }
+
+    #[test]
+    fn account_proof() {
+        let mut db = MemoryDB::new();
+        let root = build_state(&mut db);
+
+        let address = Address::from(5);
+        let proof = prove_account(&db, &root, &address).unwrap();
+        assert!(!proof.is_empty());
+        let basic = verify_account_proof(&root, &address, &proof).unwrap().unwrap();
+        assert_eq!(basic.balance, U256::from(500));
+        assert_eq!(basic.nonce, U256::from(5));
+
+        // absence is provable too.
+        let missing = Address::from(0x1000);
+        let proof = prove_account(&db, &root, &missing).unwrap();
+        assert_eq!(verify_account_proof(&root, &missing, &proof).unwrap(), None);
+
+        // a proof for another root fails.
+        let other = H256::from(1);
+        assert_eq!(
+            verify_account_proof(&other, &address, &proof),
+            Err(Error::Trie(TrieError::InvalidStateRoot(other)))
+        );
+
+        // an incomplete proof fails.
+        let mut proof = prove_account(&db, &root, &address).unwrap();
+        proof.pop();
+        match verify_account_proof(&root, &address, &proof) {
+            Err(Error::Trie(TrieError::IncompleteDatabase(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn storage_proof() {
+        let mut db = MemoryDB::new();
+        let root = build_state(&mut db);
+        let factory = Factory::default();
+
+        let address = Address::from(9);
+        let key = H128::from(9u64);
+        let proof = prove_storage(&db, &root, &factory, &address, &key).unwrap();
+        let value = verify_storage_proof(&root, &address, &key, &proof).unwrap().unwrap();
+        assert_eq!(::rlp::decode::<U128>(&value), U128::from(10));
+
+        let key = H128::from(10u64);
+        let proof = prove_storage(&db, &root, &factory, &address, &key).unwrap();
+        assert_eq!(verify_storage_proof(&root, &address, &key, &proof).unwrap(), None);
+
+        let address = Address::from(0x100);
+        let key = vec![0x01, 0x02];
+        let proof = prove_storage(&db, &root, &factory, &address, &key).unwrap();
+        let value = verify_storage_proof(&root, &address, &key, &proof).unwrap().unwrap();
+        assert_eq!(::rlp::decode::<Vec<u8>>(&value), vec![0x03]);
+
+        let address = Address::from(0x1000);
+        let proof = prove_storage(&db, &root, &factory, &address, &key).unwrap();
+        assert!(proof.storage.is_empty());
+        assert_eq!(verify_storage_proof(&root, &address, &key, &proof).unwrap(), None);
+    }
+}
@@ -0,0 +1,133 @@
+use aion_types::Address;
+use trie::{self, TrieMut};
+
+use traits::CommonAccount;
+
+/// Policy for removing touched empty accounts from the state on commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PruningPolicy {
+    /// Keep every account.
+    Never,
+    /// Remove touched accounts that are empty (EIP-161).
+    Eip161,
+    /// Like `Eip161`, but keep accounts flagged with `set_empty_but_commit`,
+    /// matching the Aion Java kernel.
+    #[default]
+    JavaKernel,
+}
+
+impl PruningPolicy {
+    /// Whether `account` should be removed from the state.
+    /// Only accounts touched since they were loaded are considered.
+    pub fn should_kill<A: CommonAccount>(&self, account: &A) -> bool {
+        if !account.is_dirty() || !account.is_empty() {
+            return false;
+        }
+        match *self {
+            PruningPolicy::Never => false,
+            PruningPolicy::Eip161 => true,
+            PruningPolicy::JavaKernel => !account.is_empty_but_commit(),
+        }
+    }
+}
+
+/// Write the dirty `accounts` to the state `trie` keyed by address, removing touched
+/// empty accounts as `policy` dictates. Storage and code must be committed first.
+/// Returns the addresses of the removed accounts.
+pub fn commit_accounts<'a, A, I>(trie: &mut TrieMut, accounts: I, policy: PruningPolicy) -> trie::Result<Vec<Address>>
+where
+    A: CommonAccount + 'a,
+    I: IntoIterator<Item = (&'a Address, &'a mut A)>,
+{
+    let mut killed = Vec::new();
+    for (address, account) in accounts {
+        if policy.should_kill(account) {
+            trace!(target: "account", "removing empty account {:?}", address);
+            trie.remove(address)?;
+            account.commit_rlp();
+            killed.push(*address);
+        } else if let Some(rlp) = account.commit_rlp() {
+            trie.insert(address, &rlp)?;
+        }
+    }
+    Ok(killed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use aion_types::{H256, U256};
+    use accounts::FVMAccount;
+    use kvdb::MemoryDB;
+    use trie::{SecTrieDBMut, Trie, SecTrieDB};
+
+    // committed state with an empty account, a funded account and an empty account
+    // flagged for the Java kernel, each touched by a zero value transfer.
+    fn commit_with(policy: PruningPolicy) -> (Vec<Address>, Vec<bool>) {
+        let mut db = MemoryDB::new();
+        let mut root = H256::default();
+        let mut accounts = BTreeMap::new();
+        for i in 1..4u64 {
+            let balance = match i {
+                2 => U256::from(10),
+                _ => U256::zero(),
+            };
+            let mut account = FVMAccount::new_basic(balance, 0.into());
+            if i == 3 {
+                account.set_empty_but_commit();
+            }
+            accounts.insert(Address::from(i), account);
+        }
+        {
+            let mut trie = SecTrieDBMut::new(&mut db, &mut root);
+            commit_accounts(&mut trie, accounts.iter_mut(), PruningPolicy::Never).unwrap();
+        }
+
+        for account in accounts.values_mut() {
+            account.add_balance(&U256::zero());
+        }
+        let killed = {
+            let mut trie = SecTrieDBMut::from_existing(&mut db, &mut root).unwrap();
+            commit_accounts(&mut trie, accounts.iter_mut(), policy).unwrap()
+        };
+        let trie = SecTrieDB::new(&db, &root).unwrap();
+        let present = accounts.keys().map(|a| trie.contains(a).unwrap()).collect();
+        (killed, present)
+    }
+
+    #[test]
+    fn pruning_never() {
+        assert_eq!(commit_with(PruningPolicy::Never), (vec![], vec![true, true, true]));
+    }
+
+    #[test]
+    fn pruning_eip161() {
+        assert_eq!(
+            commit_with(PruningPolicy::Eip161),
+            (vec![Address::from(1), Address::from(3)], vec![false, true, false])
+        );
+    }
+
+    #[test]
+    fn pruning_java_kernel() {
+        assert_eq!(
+            commit_with(PruningPolicy::JavaKernel),
+            (vec![Address::from(1)], vec![false, true, true])
+        );
+    }
+
+    #[test]
+    fn untouched_empty_account_is_kept() {
+        let mut a = FVMAccount::new_basic(0.into(), 0.into());
+        a.commit_rlp();
+        assert!(a.is_empty());
+        assert!(!PruningPolicy::Eip161.should_kill(&a));
+
+        // pending storage changes make the account non-empty instead of panicking.
+        a.set_storage(::accounts::FVMKey::Normal(1u64.into()), ::accounts::FVMValue::Normal(1u64.into()));
+        assert!(!a.is_empty());
+        assert!(!PruningPolicy::Eip161.should_kill(&a));
+    }
+}
@@ -0,0 +1,427 @@
+//! Account state snapshots.
+//!
+//! The state trie is cut into chunks of "fat" accounts: every account is stored together
+//! with its code and all entries of its storage trie, so a chunk can be restored without
+//! access to any other data. A chunk is an RLP list of `[address_hash, fat_account]` pairs
+//! compressed with snappy, where `fat_account` is
+//! `[nonce, balance, code_state, code, [[key, value, preimage?], ...], account_type?]`,
+//! followed by `transformed_code_state, transformed_code, objectgraph_state, objectgraph`
+//! for AVM accounts that carry them.
+//! Code shared by several accounts is only inlined once and referenced by hash afterwards;
+//! the same applies to transformed code and object graphs.
+
+use std::collections::{HashMap, HashSet};
+
+use aion_types::{H256, U256};
+use blake2b::{BLAKE2B_EMPTY, BLAKE2B_NULL_RLP, blake2b};
+use bytes::Bytes;
+use kvdb::{DBValue, HashStore};
+use parity_snappy as snappy;
+use rlp::{DecoderError, RlpStream, UntrustedRlp};
+use trie::{Trie, TrieDB, TrieDBMut, TrieMut};
+
+use account_db::Factory;
+use error::{Error, Result};
+
+/// Preferred size of an uncompressed snapshot chunk.
+pub const PREFERRED_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Account has no code.
+const CODE_EMPTY: u8 = 0;
+/// Code is stored inline.
+const CODE_INLINE: u8 = 1;
+/// Code was inlined by an earlier account and is referenced by hash.
+const CODE_HASH: u8 = 2;
+
+/// Split the state at `root` into compressed chunks of roughly `chunk_size` bytes.
+/// An account is never split, so a single chunk may exceed `chunk_size`.
+pub fn chunk_state(db: &HashStore, root: &H256, factory: &Factory, chunk_size: usize) -> Result<Vec<Bytes>> {
+    let trie = TrieDB::new(db, root)?;
+    let mut used_code = HashSet::new();
+    let mut chunks = Vec::new();
+    let mut entries = Vec::new();
+    let mut size = 0;
+
+    for item in trie.iter()? {
+        let (address_hash, account_rlp) = item?;
+        let address_hash = H256::from_slice(&address_hash);
+        let account_db = factory.readonly(db, address_hash);
+        let fat = to_fat_rlp(&account_rlp, &*account_db, &mut used_code)?;
+
+        let mut pair = RlpStream::new_list(2);
+        pair.append(&address_hash);
+        pair.append_raw(&fat, 1);
+        let pair = pair.out();
+
+        if size + pair.len() > chunk_size && !entries.is_empty() {
+            chunks.push(write_chunk(&entries));
+            entries.clear();
+            size = 0;
+        }
+        size += pair.len();
+        entries.push(pair);
+    }
+    if !entries.is_empty() {
+        chunks.push(write_chunk(&entries));
+    }
+
+    trace!(target: "snapshot", "chunked state {} into {} chunks", root, chunks.len());
+    Ok(chunks)
+}
+
+fn write_chunk(entries: &[Bytes]) -> Bytes {
+    let mut stream = RlpStream::new_list(entries.len());
+    for entry in entries {
+        stream.append_raw(entry, 1);
+    }
+    snappy::compress(&stream.out())
+}
+
+fn append_code(
+    stream: &mut RlpStream,
+    code_hash: H256,
+    account_db: &HashStore,
+    used_code: &mut HashSet<H256>,
+) -> Result<()>
+{
+    if code_hash == BLAKE2B_EMPTY {
+        stream.append(&CODE_EMPTY).append_empty_data();
+    } else if used_code.contains(&code_hash) {
+        stream.append(&CODE_HASH).append(&code_hash);
+    } else {
+        let code = account_db.get(&code_hash).ok_or(Error::MissingCode(code_hash))?;
+        used_code.insert(code_hash);
+        stream.append(&CODE_INLINE).append(&&*code);
+    }
+    Ok(())
+}
+
+fn to_fat_rlp(account_rlp: &[u8], account_db: &HashStore, used_code: &mut HashSet<H256>) -> Result<Bytes> {
+    let rlp = UntrustedRlp::new(account_rlp);
+    let item_count = rlp.item_count()?;
+    let fat_count = match item_count {
+        4 => 5,
+        5 => 6,
+        7 => 10,
+        _ => return Err(DecoderError::RlpIncorrectListLen.into()),
+    };
+    let nonce: U256 = rlp.val_at(0)?;
+    let balance: U256 = rlp.val_at(1)?;
+    let storage_root: H256 = rlp.val_at(2)?;
+    let code_hash: H256 = rlp.val_at(3)?;
+
+    let mut stream = RlpStream::new_list(fat_count);
+    stream.append(&nonce).append(&balance);
+    append_code(&mut stream, code_hash, account_db, used_code)?;
+
+    let storage = TrieDB::new(account_db, &storage_root)?;
+    let entries = storage.iter()?.collect::<::trie::Result<Vec<_>>>()?;
+    stream.begin_list(entries.len());
+    for (key, value) in entries {
+        // keep preimages of secure and fat tries so storage stays iterable.
+        match account_db.get(&blake2b(&key)) {
+            Some(preimage) => {
+                stream.begin_list(3).append(&key).append(&&*value).append(&&*preimage);
+            }
+            None => {
+                stream.begin_list(2).append(&key).append(&&*value);
+            }
+        }
+    }
+
+    if item_count >= 5 {
+        stream.append_raw(rlp.at(4)?.as_raw(), 1);
+    }
+    if item_count == 7 {
+        append_code(&mut stream, rlp.val_at(5)?, account_db, used_code)?;
+        append_code(&mut stream, rlp.val_at(6)?, account_db, used_code)?;
+    }
+    Ok(stream.out())
+}
+
+/// Rebuilds state from snapshot chunks.
+/// Chunks may be fed in any order; `finalize` checks the result.
+pub struct StateRebuilder<'db> {
+    db: &'db mut HashStore,
+    factory: Factory,
+    state_root: H256,
+    /// code hash -> address hash of an account the code was restored into.
+    known_code: HashMap<H256, H256>,
+    /// code hash -> address hashes of accounts still waiting for that code.
+    missing_code: HashMap<H256, Vec<H256>>,
+}
+
+impl<'db> StateRebuilder<'db> {
+    /// Create a rebuilder writing into `db` with account dbs created by `factory`.
+    pub fn new(db: &'db mut HashStore, factory: Factory) -> Self {
+        StateRebuilder {
+            db: db,
+            factory: factory,
+            state_root: BLAKE2B_NULL_RLP,
+            known_code: HashMap::new(),
+            missing_code: HashMap::new(),
+        }
+    }
+
+    /// Root of the state restored so far.
+    pub fn state_root(&self) -> H256 { self.state_root }
+
+    /// Restore all accounts of a compressed chunk.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<()> {
+        let raw = snappy::decompress(chunk).map_err(|_| Error::InvalidChunk)?;
+        let rlp = UntrustedRlp::new(&raw);
+
+        let mut accounts = Vec::with_capacity(rlp.item_count()?);
+        for pair in rlp.iter() {
+            let address_hash: H256 = pair.val_at(0)?;
+            let account_rlp = self.restore_account(address_hash, &pair.at(1)?)?;
+            accounts.push((address_hash, account_rlp));
+        }
+
+        let mut root = self.state_root;
+        {
+            let mut trie = match root == BLAKE2B_NULL_RLP {
+                true => TrieDBMut::new(&mut *self.db, &mut root),
+                false => TrieDBMut::from_existing(&mut *self.db, &mut root)?,
+            };
+            for (address_hash, account_rlp) in accounts {
+                trie.insert(&address_hash, &account_rlp)?;
+            }
+        }
+        self.state_root = root;
+        Ok(())
+    }
+
+    /// Restore code and storage of one fat account and return its plain RLP.
+    fn restore_account(&mut self, address_hash: H256, fat: &UntrustedRlp) -> Result<Bytes> {
+        let item_count = fat.item_count()?;
+        if item_count != 5 && item_count != 6 && item_count != 10 {
+            return Err(DecoderError::RlpIncorrectListLen.into());
+        }
+        let nonce: U256 = fat.val_at(0)?;
+        let balance: U256 = fat.val_at(1)?;
+        let code_hash = self.restore_code(address_hash, fat, 2)?;
+
+        let mut storage_root = BLAKE2B_NULL_RLP;
+        {
+            let mut account_db = self.factory.create(&mut *self.db, address_hash);
+            let mut storage = TrieDBMut::new(&mut *account_db, &mut storage_root);
+            for entry in fat.at(4)?.iter() {
+                let key: Bytes = entry.val_at(0)?;
+                let value: Bytes = entry.val_at(1)?;
+                storage.insert(&key, &value)?;
+                if entry.item_count()? == 3 {
+                    let preimage: Bytes = entry.val_at(2)?;
+                    storage
+                        .db_mut()
+                        .emplace(blake2b(&key), DBValue::from_slice(&preimage));
+                }
+            }
+        }
+
+        let mut stream = RlpStream::new_list(match item_count {
+            10 => 7,
+            _ => item_count - 1,
+        });
+        stream
+            .append(&nonce)
+            .append(&balance)
+            .append(&storage_root)
+            .append(&code_hash);
+        if item_count >= 6 {
+            stream.append_raw(fat.at(5)?.as_raw(), 1);
+        }
+        if item_count == 10 {
+            let transformed_code_hash = self.restore_code(address_hash, fat, 6)?;
+            let objectgraph_hash = self.restore_code(address_hash, fat, 8)?;
+            stream.append(&transformed_code_hash).append(&objectgraph_hash);
+        }
+        Ok(stream.out())
+    }
+
+    /// Restore the code whose state is at `index` of `fat` into the account db and return its hash.
+    fn restore_code(&mut self, address_hash: H256, fat: &UntrustedRlp, index: usize) -> Result<H256> {
+        let code_state: u8 = fat.val_at(index)?;
+        let code_hash = match code_state {
+            CODE_EMPTY => BLAKE2B_EMPTY,
+            CODE_INLINE => {
+                let code: Bytes = fat.val_at(index + 1)?;
+                let code_hash = self.factory.create(&mut *self.db, address_hash).insert(&code);
+                for waiting in self.missing_code.remove(&code_hash).unwrap_or_default() {
+                    self.factory
+                        .create(&mut *self.db, waiting)
+                        .emplace(code_hash, DBValue::from_slice(&code));
+                }
+                self.known_code.insert(code_hash, address_hash);
+                code_hash
+            }
+            CODE_HASH => {
+                let code_hash: H256 = fat.val_at(index + 1)?;
+                let holder = self.known_code.get(&code_hash).cloned();
+                match holder {
+                    Some(holder) => {
+                        let code = self
+                            .factory
+                            .readonly(&*self.db, holder)
+                            .get(&code_hash)
+                            .ok_or(Error::MissingCode(code_hash))?;
+                        self.factory.create(&mut *self.db, address_hash).emplace(code_hash, code);
+                    }
+                    None => {
+                        self.missing_code
+                            .entry(code_hash)
+                            .or_default()
+                            .push(address_hash);
+                    }
+                }
+                code_hash
+            }
+            _ => return Err(DecoderError::Custom("invalid code state").into()),
+        };
+        Ok(code_hash)
+    }
+
+    /// Check that all referenced code was restored and the state has `expected_root`.
+    pub fn finalize(self, expected_root: &H256) -> Result<()> {
+        if let Some(code_hash) = self.missing_code.keys().next() {
+            return Err(Error::MissingCode(*code_hash));
+        }
+        match self.state_root == *expected_root {
+            true => Ok(()),
+            false => {
+                Err(Error::StateRootMismatch {
+                    expected: *expected_root,
+                    found: self.state_root,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aion_types::Address;
+    use kvdb::MemoryDB;
+    use trie::{SecTrieDBMut, TrieFactory, TrieSpec};
+    use accounts::{AVMAccount, FVMAccount, FVMKey, FVMValue};
+    use account_db::AccountDB;
+    use state_cache::StateCache;
+    use traits::CommonAccount;
+
+    fn build_state(db: &mut MemoryDB) -> H256 {
+        let cache = StateCache::default();
+        let fat = TrieFactory::new(TrieSpec::Fat);
+        let mut accounts = Vec::new();
+
+        for i in 0..4u64 {
+            let address = Address::from(i + 1);
+            let mut a = AVMAccount::new_contract(i.into(), 0.into());
+            for j in 0..8u8 {
+                a.set_storage(vec![j], vec![j + 1, i as u8]);
+            }
+            // two accounts share code.
+            a.init_code(vec![0x60, (i % 3) as u8]);
+            if i % 2 == 0 {
+                a.init_transformed_code(vec![0x70, (i % 3) as u8]);
+                a.set_objectgraph(vec![0x80, i as u8]);
+            }
+            {
+                let mut db = ::account_db::AccountDBMut::new(db, &address);
+                a.commit_storage_shared(&cache, &address, &fat, &mut db).unwrap();
+                a.commit_code(&mut db);
+            }
+            accounts.push((address, a.rlp()));
+        }
+
+        let address = Address::from(0x10);
+        let mut f = FVMAccount::new_contract(0.into(), 0.into());
+        f.set_storage(FVMKey::Normal(0x01u64.into()), FVMValue::Normal(0x11u64.into()));
+        {
+            let mut db = ::account_db::AccountDBMut::new(db, &address);
+            f.commit_storage_shared(&cache, &address, &Default::default(), &mut db).unwrap();
+        }
+        accounts.push((address, f.rlp()));
+
+        // legacy account without type marker.
+        let mut legacy = RlpStream::new_list(4);
+        legacy
+            .append(&U256::from(1))
+            .append(&U256::from(100))
+            .append(&BLAKE2B_NULL_RLP)
+            .append(&BLAKE2B_EMPTY);
+        accounts.push((Address::from(0x20), legacy.out()));
+
+        let mut root = H256::default();
+        {
+            let mut trie = SecTrieDBMut::new(db, &mut root);
+            for (address, rlp) in accounts {
+                trie.insert(&address, &rlp).unwrap();
+            }
+        }
+        root
+    }
+
+    #[test]
+    fn snapshot_roundtrip() {
+        let mut db = MemoryDB::new();
+        let root = build_state(&mut db);
+        let factory = Factory::default();
+
+        let chunks = chunk_state(&db, &root, &factory, 256).unwrap();
+        assert!(chunks.len() > 1);
+
+        // feed in reverse so code references precede the inlined code.
+        let mut restored = MemoryDB::new();
+        {
+            let mut rebuilder = StateRebuilder::new(&mut restored, factory.clone());
+            for chunk in chunks.iter().rev() {
+                rebuilder.feed(chunk).unwrap();
+            }
+            assert_eq!(rebuilder.state_root(), root);
+            rebuilder.finalize(&root).unwrap();
+        }
+
+        let address = Address::from(3);
+        let trie = ::trie::SecTrieDB::new(&restored, &root).unwrap();
+        let mut a = AVMAccount::from_rlp(&trie.get(&address).unwrap().unwrap());
+        let account_db = AccountDB::from_hash(&restored, blake2b(address));
+        assert_eq!(*a.cache_code(&account_db).unwrap(), vec![0x60, 0x02]);
+        assert_eq!(*a.cache_transformed_code(&account_db).unwrap(), vec![0x70, 0x02]);
+        assert_eq!(*a.cache_objectgraph(&account_db).unwrap(), vec![0x80, 0x02]);
+        assert_eq!(a.storage_iter(&account_db).unwrap().count(), 8);
+        let b = AVMAccount::from_rlp(&trie.get(&Address::from(2)).unwrap().unwrap());
+        assert_eq!(b.transformed_code_hash(), BLAKE2B_EMPTY);
+
+        // rechunking the restored state gives the same chunks.
+        assert_eq!(chunk_state(&restored, &root, &factory, 256).unwrap(), chunks);
+    }
+
+    #[test]
+    fn snapshot_wrong_root() {
+        let mut db = MemoryDB::new();
+        let root = build_state(&mut db);
+        let factory = Factory::default();
+        let chunks = chunk_state(&db, &root, &factory, PREFERRED_CHUNK_SIZE).unwrap();
+        assert_eq!(chunks.len(), 1);
+
+        let mut restored = MemoryDB::new();
+        let mut rebuilder = StateRebuilder::new(&mut restored, factory);
+        rebuilder.feed(&chunks[0]).unwrap();
+        let expected = H256::from(1);
+        assert_eq!(
+            rebuilder.finalize(&expected),
+            Err(Error::StateRootMismatch {
+                expected: expected,
+                found: root,
+            })
+        );
+    }
+
+    #[test]
+    fn snapshot_invalid_chunk() {
+        let mut restored = MemoryDB::new();
+        let mut rebuilder = StateRebuilder::new(&mut restored, Factory::default());
+        assert_eq!(rebuilder.feed(&[0xff, 0x00, 0x01]), Err(Error::InvalidChunk));
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use aion_types::{Address, H256};
+use bytes::Bytes;
+use lru_cache::LruCache;
+use parking_lot::Mutex;
+
+/// Default number of storage items kept in a `StateCache`.
+pub const STATE_CACHE_STORAGE_ITEMS: usize = 65536;
+/// Default number of contract codes kept in a `StateCache`.
+pub const STATE_CACHE_CODE_ITEMS: usize = 1024;
+
+/// Hit and miss counters of a `StateCache`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Storage lookups answered by the cache.
+    pub storage_hits: usize,
+    /// Storage lookups that had to go to the trie.
+    pub storage_misses: usize,
+    /// Code lookups answered by the cache.
+    pub code_hits: usize,
+    /// Code lookups that had to go to the database.
+    pub code_misses: usize,
+}
+
+/// Size-bounded cache of committed storage values and contract code,
+/// shared by all accounts and kept across blocks.
+///
+/// Storage entries are keyed by (address, storage key) and remember the storage root
+/// they were read at, so a lookup only hits when the account is still at that root.
+/// Committing storage through `commit_storage_shared` moves the changed keys to the
+/// new root and leaves all other entries of the account to miss.
+/// Code is keyed by `code_hash` and never goes stale.
+pub struct StateCache {
+    storage: Mutex<LruCache<(Address, Bytes), (H256, Bytes)>>,
+    code: Mutex<LruCache<H256, Arc<Bytes>>>,
+    storage_hits: AtomicUsize,
+    storage_misses: AtomicUsize,
+    code_hits: AtomicUsize,
+    code_misses: AtomicUsize,
+}
+
+impl Default for StateCache {
+    fn default() -> Self { StateCache::new(STATE_CACHE_STORAGE_ITEMS, STATE_CACHE_CODE_ITEMS) }
+}
+
+impl StateCache {
+    /// Create a cache holding at most `storage_items` storage values and `code_items` codes.
+    pub fn new(storage_items: usize, code_items: usize) -> Self {
+        StateCache {
+            storage: Mutex::new(LruCache::new(storage_items)),
+            code: Mutex::new(LruCache::new(code_items)),
+            storage_hits: AtomicUsize::new(0),
+            storage_misses: AtomicUsize::new(0),
+            code_hits: AtomicUsize::new(0),
+            code_misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Raw trie value of `key` in the storage of `address` at `storage_root`.
+    /// An empty value means the key is known to be absent.
+    pub fn storage(&self, address: &Address, storage_root: &H256, key: &[u8]) -> Option<Bytes> {
+        let value = match self.storage.lock().get_mut(&(*address, key.to_vec())) {
+            Some(&mut (ref root, ref value)) if root == storage_root => Some(value.clone()),
+            _ => None,
+        };
+        match value {
+            Some(_) => self.storage_hits.fetch_add(1, Ordering::Relaxed),
+            None => self.storage_misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    /// Cache the raw trie value of `key` in the storage of `address` at `storage_root`.
+    pub fn insert_storage(&self, address: Address, storage_root: H256, key: Bytes, value: Bytes) {
+        self.storage.lock().insert((address, key), (storage_root, value));
+    }
+
+    /// Code with the given hash.
+    pub fn code(&self, code_hash: &H256) -> Option<Arc<Bytes>> {
+        let code = self.code.lock().get_mut(code_hash).cloned();
+        match code {
+            Some(_) => self.code_hits.fetch_add(1, Ordering::Relaxed),
+            None => self.code_misses.fetch_add(1, Ordering::Relaxed),
+        };
+        code
+    }
+
+    /// Cache code under its hash.
+    pub fn insert_code(&self, code_hash: H256, code: Arc<Bytes>) { self.code.lock().insert(code_hash, code); }
+
+    /// Drop all cached entries. Counters are kept.
+    pub fn clear(&self) {
+        self.storage.lock().clear();
+        self.code.lock().clear();
+    }
+
+    /// Snapshot of the hit and miss counters.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            storage_hits: self.storage_hits.load(Ordering::Relaxed),
+            storage_misses: self.storage_misses.load(Ordering::Relaxed),
+            code_hits: self.code_hits.load(Ordering::Relaxed),
+            code_misses: self.code_misses.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -3,11 +3,18 @@ use std::sync::Arc;
 use aion_types::{Address, H256, U256};
 use bytes::Bytes;
 use kvdb::{HashStore};
+use generic::AccType;
+use error::{Error, Result};
+use state_cache::StateCache;
+use access::ReadWriteTracker;
 
 pub trait CommonAccount: Sync + Send {
 
     fn from_rlp(rlp: &[u8]) -> Self;
 
+    /// Type marker of this account.
+    fn account_type(&self) -> AccType;
+
     /// Set this account's code to the given code.
     /// NOTE: Account should have been created with `new_contract()`
     fn init_code(&mut self, code: Bytes);
@@ -36,6 +43,10 @@ pub trait CommonAccount: Sync + Send {
     /// Provide a database to get `code_hash`. Should not be called if it is a contract without code.
     fn cache_code(&mut self, db: &HashStore) -> Option<Arc<Bytes>>;
 
+    /// Like `cache_code`, but looks the code up in the shared `StateCache` first
+    /// and adds it there after reading it from `db`.
+    fn cache_code_shared(&mut self, cache: &StateCache, db: &HashStore) -> Option<Arc<Bytes>>;
+
     /// Provide code to cache. For correctness, should be the correct code for the
     /// account.
     fn cache_given_code(&mut self, code: Arc<Bytes>);
@@ -44,10 +55,15 @@ pub trait CommonAccount: Sync + Send {
     fn cache_code_size(&mut self, db: &HashStore) -> bool;
 
     /// Check if account has zero nonce, balance, no code and no storage.
-    ///
-    /// NOTE: Will panic if `!self.storage_is_clean()`
+    /// Uncommitted storage changes count as storage.
     fn is_empty(&self) -> bool;
 
+    /// Whether the account is kept in the state even if empty, as the Java kernel does.
+    fn is_empty_but_commit(&self) -> bool;
+
+    /// Keep the account in the state even if empty, under the Java kernel pruning policy.
+    fn set_empty_but_commit(&mut self);
+
     /// Check if account has zero nonce, balance, no code.
     fn is_null(&self) -> bool;
 
@@ -67,6 +83,18 @@ pub trait CommonAccount: Sync + Send {
     /// Panics if balance is less than `x`
     fn sub_balance(&mut self, x: &U256);
 
+    /// Increment the nonce of the account by one.
+    /// Returns an error instead of wrapping around on overflow.
+    fn checked_inc_nonce(&mut self) -> Result<()>;
+
+    /// Increase account balance.
+    /// Returns an error and leaves the balance untouched on overflow.
+    fn checked_add_balance(&mut self, x: &U256) -> Result<()>;
+
+    /// Decrease account balance.
+    /// Returns an error and leaves the balance untouched if balance is less than `x`.
+    fn checked_sub_balance(&mut self, x: &U256) -> Result<()>;
+
     // /// Commit the `storage_changes` to the backing DB and update `storage_root`.
     // fn commit_storage(
     //     &mut self,
@@ -82,12 +110,25 @@ pub trait CommonAccount: Sync + Send {
     /// Export to RLP.
     fn rlp(&self) -> Bytes;
 
+    /// Whether balance, nonce, storage or code changed since the account was
+    /// loaded or last returned by `commit_rlp`.
+    fn is_dirty(&self) -> bool;
+
+    /// RLP to write back to the state trie, or `None` if the account is clean.
+    /// Storage and code should be committed first; the account is clean afterwards
+    /// unless uncommitted changes remain.
+    fn commit_rlp(&mut self) -> Option<Bytes>;
+
     // /// Clone basic account data
     // fn clone_basic(&self) -> Self;
 
     /// Clone account data and dirty storage keys
     fn clone_dirty(&self) -> Self;
 
+    /// Record reads and writes of this account and its storage in `tracker`,
+    /// starting with a read of `address`.
+    fn track_access(&mut self, address: Address, tracker: Arc<ReadWriteTracker>);
+
     // /// Clone account data, dirty storage keys and cached storage keys.
     // fn clone_all(&self) -> Self;
 
@@ -95,4 +136,27 @@ pub trait CommonAccount: Sync + Send {
     // /// Basic account data and all modifications are overwritten
     // /// with new values.
     // fn overwrite_wigh(&mut self, pther: Self);
-}
\ No newline at end of file
+}
+
+/// Move `value` from the balance of `from` to the balance of `to`.
+/// Neither account is modified unless both sides of the transfer succeed.
+pub fn transfer<A, B>(from: &mut A, to: &mut B, value: &U256) -> Result<()>
+where
+    A: CommonAccount,
+    B: CommonAccount,
+{
+    if from.balance() < value {
+        return Err(Error::BalanceUnderflow {
+            balance: *from.balance(),
+            amount: *value,
+        });
+    }
+    if to.balance().checked_add(*value).is_none() {
+        return Err(Error::BalanceOverflow {
+            balance: *to.balance(),
+            amount: *value,
+        });
+    }
+    from.checked_sub_balance(value)?;
+    to.checked_add_balance(value)
+}
@@ -0,0 +1,118 @@
+/*******************************************************************************
+ * Copyright (c) 2018-2019 Aion foundation.
+ *
+ *     This file is part of the aion network project.
+ *
+ *     The aion network project is free software: you can redistribute it
+ *     and/or modify it under the terms of the GNU General Public License
+ *     as published by the Free Software Foundation, either version 3 of
+ *     the License, or any later version.
+ *
+ *     The aion network project is distributed in the hope that it will
+ *     be useful, but WITHOUT ANY WARRANTY; without even the implied
+ *     warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ *     See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License
+ *     along with the aion network project source files.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ ******************************************************************************/
+
+//! Manifest of a repository backup: the names of the backed up dbs and the blake2b
+//! checksum of every file, so a copy moved to another machine can be checked on restore.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use blake2b::blake2b_buffer;
+use error::Error;
+use Result;
+
+/// manifest file in the backup directory.
+pub const MANIFEST_FILE: &str = "BACKUP_MANIFEST";
+
+fn checksum(file: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(file)?);
+    blake2b_buffer(&mut reader).map(|hash| format!("{:x}", hash))
+}
+
+/// Whether `file` names a regular file right in the backup directory `path`, so that a
+/// manifest from elsewhere can not point outside of it.
+fn is_backup_file(path: &str, file: &str) -> bool {
+    let name = Path::new(file);
+    let plain = file != MANIFEST_FILE
+        && !file.contains(['/', '\\'])
+        && name.file_name() == Some(name.as_os_str());
+    plain && fs::symlink_metadata(Path::new(path).join(file))
+        .map(|metadata| metadata.file_type().is_file())
+        .unwrap_or(false)
+}
+
+fn manifest_error(path: &str, desc: String) -> Error {
+    Error::OpenError {
+        name: path.into(),
+        desc: desc,
+    }
+}
+
+/// Write the manifest of the backup at `path`, covering every file in it.
+pub fn write_manifest(path: &str, db_names: &[String]) -> Result<()> {
+    let write = || -> io::Result<()> {
+        let mut manifest = String::new();
+        for db_name in db_names {
+            manifest.push_str(&format!("db {}\n", db_name));
+        }
+        let mut files = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<_>>>()?;
+        files.sort();
+        for file in files {
+            let hash = checksum(&Path::new(path).join(&file))?;
+            manifest.push_str(&format!("file {} {}\n", hash, file));
+        }
+        fs::write(Path::new(path).join(MANIFEST_FILE), manifest)
+    };
+    write().map_err(|e| {
+        Error::FlushError {
+            name: path.into(),
+            desc: format!("can not write {}: {}", MANIFEST_FILE, e),
+        }
+    })
+}
+
+/// Check the files of the backup at `path` against its manifest. Returns the names of
+/// the backed up dbs and the files to restore.
+pub fn verify_manifest(path: &str) -> Result<(Vec<String>, Vec<String>)> {
+    let manifest = fs::read_to_string(Path::new(path).join(MANIFEST_FILE))
+        .map_err(|e| manifest_error(path, format!("can not read {}: {}", MANIFEST_FILE, e)))?;
+    let mut db_names = Vec::new();
+    let mut files = Vec::new();
+    for line in manifest.lines() {
+        let parts = line.splitn(3, ' ').collect::<Vec<_>>();
+        match parts.as_slice() {
+            ["db", db_name] => db_names.push(db_name.to_string()),
+            ["file", hash, file] => {
+                if !is_backup_file(path, file) {
+                    return Err(manifest_error(path, format!("invalid backup file {}", file)));
+                }
+                match checksum(&Path::new(path).join(file)) {
+                    Ok(ref actual) if actual == hash => files.push(file.to_string()),
+                    Ok(_) => return Err(manifest_error(path, format!("checksum mismatch of {}", file))),
+                    Err(e) => return Err(manifest_error(path, format!("can not read {}: {}", file, e))),
+                }
+            }
+            _ => return Err(manifest_error(path, format!("invalid manifest line: {}", line))),
+        }
+    }
+    let unlisted = fs::read_dir(path)
+        .map_err(|e| manifest_error(path, e.to_string()))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .find(|file| file != MANIFEST_FILE && !files.contains(file));
+    if let Some(file) = unlisted {
+        return Err(manifest_error(path, format!("{} is not in the manifest", file)));
+    }
+    Ok((db_names, files))
+}
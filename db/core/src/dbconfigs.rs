@@ -22,6 +22,7 @@
 #[cfg(target_os = "linux")]
 use std::path::PathBuf;
 use std::path::Path;
+use std::str::FromStr;
 /// A single db in dbrepository ' config
 #[derive(Clone, Debug)]
 pub struct RepositoryConfig {
@@ -29,6 +30,8 @@ pub struct RepositoryConfig {
     pub db_name: String,
     /// db config
     pub db_config: DatabaseConfig,
+    /// workload preset applied on top of `db_config`
+    pub profile: DbProfile,
     /// db path
     pub db_path: String,
 }
@@ -38,8 +41,12 @@ pub struct RepositoryConfig {
 pub struct DatabaseConfig {
     /// How many files rocksdb can open at one time.
     pub max_open_files: i32,
-    /// Memory budget for block based cache size (MB).
+    /// Memory budget (bytes) of the memtables of a column family. The config of the db also
+    /// sizes the block cache its column families share.
     pub memory_budget: usize,
+    /// Share of the memory budget given to a block cache of the column family's own,
+    /// instead of the shared one.
+    pub cache_share: Option<f64>,
     /// Block based cache size (MB).
     pub block_size: usize,
     /// Compact options.
@@ -72,8 +79,24 @@ pub struct DatabaseConfig {
     pub disable_auto_compactions: bool,
     /// Disable wal log may cause data loss during recovery.
     pub wal: bool,
-    /// Disable database compress.
-    pub disable_compress: bool,
+    /// How the sst files are compressed.
+    pub compression: CompressionConfig,
+    /// Bloom filter bits per key, if any.
+    pub bloom_filter_bits: Option<i32>,
+    /// Length of the key prefix the bloom filters are built on, instead of the whole key.
+    pub prefix_len: Option<usize>,
+    /// How writes reach the disk.
+    pub write_mode: WriteMode,
+}
+
+/// How writes reach the disk.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WriteMode {
+    /// Every write is committed to rocksdb at once, in one batch with the wal on.
+    Durable,
+    /// Writes are kept in memory and committed on flush, using the wal only if `wal` is set.
+    /// Unflushed writes are lost on a crash.
+    Buffered,
 }
 
 impl Default for DatabaseConfig {
@@ -81,6 +104,7 @@ impl Default for DatabaseConfig {
         DatabaseConfig {
             max_open_files: 4096,
             memory_budget: 128 * 1024 * 1024,
+            cache_share: None,
             block_size: 16 * 1024,
             compact_options: CompactionProfile::default(),
             use_fsync: false,
@@ -97,7 +121,124 @@ impl Default for DatabaseConfig {
             max_background_flushes: 4,
             disable_auto_compactions: false,
             wal: false,
-            disable_compress: false,
+            compression: CompressionConfig::default(),
+            bloom_filter_bits: None,
+            prefix_len: None,
+            write_mode: WriteMode::Durable,
+        }
+    }
+}
+
+/// Workload preset of a single db, selectable by name.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum DbProfile {
+    /// `DatabaseConfig` as it is.
+    #[default]
+    General,
+    /// Random reads of hashed keys, like the state db: bloom filters, a memtable
+    /// bloom on the 32 byte hash, small blocks and the whole memory budget as cache.
+    PointLookup,
+    /// Keys written once and mostly read in order, like blocks: bloom filters against
+    /// misses, large blocks and a quarter of the memory budget as cache.
+    AppendOnly,
+    /// A few small entries: small blocks and little cache.
+    Metadata,
+}
+
+impl FromStr for DbProfile {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<DbProfile, String> {
+        match name {
+            "general" => Ok(DbProfile::General),
+            "point_lookup" => Ok(DbProfile::PointLookup),
+            "append_only" => Ok(DbProfile::AppendOnly),
+            "metadata" => Ok(DbProfile::Metadata),
+            _ => Err(format!("unknown db profile {}", name)),
+        }
+    }
+}
+
+impl DbProfile {
+    /// Name of the profile, as parsed by `from_str`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            DbProfile::General => "general",
+            DbProfile::PointLookup => "point_lookup",
+            DbProfile::AppendOnly => "append_only",
+            DbProfile::Metadata => "metadata",
+        }
+    }
+
+    /// `config` tuned for the workload.
+    pub fn apply(&self, config: &DatabaseConfig) -> DatabaseConfig {
+        // (bloom filter bits, prefix length, block size, share of the memory budget as cache)
+        let (bloom_filter_bits, prefix_len, block_size, cache_share) = match *self {
+            DbProfile::General => return config.clone(),
+            DbProfile::PointLookup => (Some(10), Some(32), 4 * 1024, 1.0),
+            DbProfile::AppendOnly => (Some(10), None, 64 * 1024, 0.25),
+            DbProfile::Metadata => (None, None, 4 * 1024, 0.05),
+        };
+        DatabaseConfig {
+            bloom_filter_bits: bloom_filter_bits,
+            prefix_len: prefix_len,
+            block_size: block_size,
+            cache_share: Some(cache_share),
+            ..config.clone()
+        }
+    }
+}
+
+/// Compression algorithm of an sst file. Lz4 and zstd need rocksdb built with them,
+/// otherwise opening the db fails.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CompressionType {
+    /// Store blocks as they are.
+    None,
+    /// Fast, moderate ratio.
+    Snappy,
+    /// Faster than snappy, similar ratio.
+    Lz4,
+    /// Slower, best ratio.
+    Zstd,
+}
+
+impl CompressionType {
+    /// Name of the algorithm in rocksdb options.
+    pub fn rocksdb_name(&self) -> &'static str {
+        match *self {
+            CompressionType::None => "kNoCompression",
+            CompressionType::Snappy => "kSnappyCompression",
+            CompressionType::Lz4 => "kLZ4Compression",
+            CompressionType::Zstd => "kZSTD",
+        }
+    }
+}
+
+/// Dictionary compression for the zstd levels.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ZstdDictionary {
+    /// Maximum dictionary size (bytes).
+    pub max_dict_bytes: usize,
+    /// Sampled data to train the dictionary on (bytes), 0 to use the samples as dictionary.
+    pub max_train_bytes: usize,
+}
+
+/// Compression of the sst files of a db.
+#[derive(Clone, PartialEq, Debug)]
+pub struct CompressionConfig {
+    /// Algorithm by level, from level 0. Deeper levels use the last one, none if empty.
+    pub per_level: Vec<CompressionType>,
+    /// Dictionary for the zstd levels, if any.
+    pub zstd_dictionary: Option<ZstdDictionary>,
+}
+
+impl Default for CompressionConfig {
+    /// Snappy on every level.
+    fn default() -> CompressionConfig {
+        CompressionConfig {
+            per_level: vec![CompressionType::Snappy],
+            zstd_dictionary: None,
         }
     }
 }
@@ -21,26 +21,34 @@
 
 use rockskvdb;
 use mockkvdb;
-use std::collections::{HashMap,BTreeMap};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
 use parking_lot::RwLock;
 
 use super::{Result, DBValue};
-use traits::{KeyValueDAO, KeyValueDB};
-use dbconfigs::RepositoryConfig;
+use traits::{KeyValueDAO, KeyValueDB, KeyValueSnapshot};
+use dbconfigs::{DbProfile, RepositoryConfig};
+use dbstats::DbStats;
+use backup;
 use dbtransaction::{DBTransaction, DBOp};
 use error::Error;
 use MemoryDB;
 
 type DB = rockskvdb::Rockskvdb;
+type DBSnapshot<'a> = rockskvdb::RockskvdbSnapshot<'a>;
 type DbName = String;
 type MockDb = mockkvdb::Mockkvdb;
 
+/// file in the repository path listing the name and profile of all dbs, one per line.
+const DB_NAMES_FILE: &str = "DB_NAMES";
+
 /// db repository
 pub struct DbRepository {
-    /// rocksdb in repository, and btreemap is faster than hashmap when searching.
-    dbs: BTreeMap<DbName, RwLock<DB>>,
-    /// db flush priority, cause Btreemap would sorted key by Dictionary order.
-    db_priority: Vec<DbName>,
+    /// a single rocksdb with one column family per db name, so that a transaction
+    /// touching several dbs is committed atomically. `None` once closed.
+    db: Option<DB>,
     /// dbs config, useful in reopening db.
     configs: Vec<RepositoryConfig>,
 }
@@ -52,6 +60,7 @@ pub struct MockDbRepository {
     configs: Vec<String>,
 }
 
+/// db repository in memory, ordered like rocksdb, useful in tests
 pub struct MemoryDBRepository {
     dbs: HashMap<DbName, RwLock<MemoryDB>>,
     configs: Vec<String>,
@@ -64,79 +73,609 @@ impl MemoryDBRepository {
             configs: Vec::new(),
         }
     }
+    /// init db repository
+    pub fn init(configs: Vec<String>) -> Self {
+        let dbs = configs
+            .iter()
+            .map(|db_name| (db_name.clone(), RwLock::new(MemoryDB::new())))
+            .collect();
+        MemoryDBRepository {
+            dbs: dbs,
+            configs: configs,
+        }
+    }
     fn flush(&self) -> Result<()> { Ok(()) }
 
     #[cfg(test)]
     fn close_all(&mut self) {}
 
     #[cfg(test)]
-    fn open_all(&mut self) {}
+    fn open_all(&mut self) -> Result<()> { Ok(()) }
 }
 
 impl DbRepository {
-    /// insert a db to the repository
-    pub fn insert_db(&mut self, _configs: Vec<RepositoryConfig>) -> Result<()> { unimplemented!() }
-    /// init repository
-    pub fn init(configs: Vec<RepositoryConfig>) -> Result<DbRepository> {
-        let dbconfigs = configs.clone();
-        let mut dbs = BTreeMap::new();
-        let mut db_names = vec![];
+    /// insert dbs to the repository at runtime. They must live at the repository path and
+    /// are reopened by later `init`s even if missing from their configs.
+    pub fn insert_db(&mut self, configs: Vec<RepositoryConfig>) -> Result<()> {
+        if self.configs.is_empty() {
+            *self = DbRepository::init(configs)?;
+            return Ok(());
+        }
         for config in configs {
-            match rockskvdb::Rockskvdb::open(&config.db_config, &config.db_path) {
-                Ok(db) => {
-                    dbs.insert(config.db_name.clone(), RwLock::new(db));
-                    db_names.push(config.db_name.clone());
-                }
-                Err(e) => {
+            if config.db_path != self.configs[0].db_path {
+                return Err(Error::OpenError {
+                    name: config.db_name,
+                    desc: format!("db path {} differs from repository path {}", config.db_path, self.configs[0].db_path),
+                });
+            }
+            if self.configs.iter().any(|c| c.db_name == config.db_name) {
+                return Err(Error::OpenError {
+                    name: config.db_name,
+                    desc: "db already exists".into(),
+                });
+            }
+            // list the db before creating it, so that a crash in between never leaves a
+            // column family that the next open does not know about.
+            let mut configs = self.configs.clone();
+            configs.push(config.clone());
+            write_db_names(&config.db_path, &configs)?;
+            if let Some(ref mut db) = self.db {
+                if let Err(e) = db.create_column(&config.db_name, &config.profile.apply(&config.db_config)) {
+                    let _ = write_db_names(&config.db_path, &self.configs);
                     return Err(Error::OpenError {
                         name: config.db_name,
                         desc: e,
-                    })
+                    });
                 }
-            };
+            }
+            self.configs = configs;
         }
-        let dbrep = DbRepository {
-            dbs: dbs,
-            db_priority: db_names,
-            configs: dbconfigs,
+        Ok(())
+    }
+    /// drop a db and all of its data from the repository.
+    pub fn drop_db(&mut self, db_name: &str) -> Result<()> {
+        let index = match self.configs.iter().position(|c| c.db_name == db_name) {
+            Some(index) => index,
+            None => return Err(Error::NotFound(db_name.into())),
         };
-        Ok(dbrep)
+        match self.db {
+            Some(ref mut db) => {
+                db.drop_column(db_name).map_err(|e| {
+                    Error::FlushError {
+                        name: db_name.into(),
+                        desc: e,
+                    }
+                })?
+            }
+            None => return Err(Error::Other("repository closed".into())),
+        }
+        let config = self.configs.remove(index);
+        write_db_names(&config.db_path, &self.configs)
     }
-    /// flush overlay to disk
-    fn flush(&self) -> Result<()> {
-        for db_name in self.db_priority.clone() {
-            match self.dbs.get(&*db_name) {
-                Some(db) => {
-                    let mut db = db.write();
-                    db.flush().map_err(|e| {
-                        Error::FlushError {
-                            name: db_name,
-                            desc: e,
-                        }
-                    })?;
+    /// init repository. All dbs share the rocksdb at the first config's path, each in its
+    /// own column family tuned by its `db_config` and `profile`. The first config also sets
+    /// the options of the rocksdb itself, and of dbs inserted at runtime that `configs` does
+    /// not list, which keep their profile.
+    pub fn init(mut configs: Vec<RepositoryConfig>) -> Result<DbRepository> {
+        if let Some(first) = configs.first().cloned() {
+            for (db_name, profile) in read_db_names(&first.db_path)? {
+                if !configs.iter().any(|c| c.db_name == db_name) {
+                    configs.push(RepositoryConfig {
+                        db_name: db_name,
+                        db_config: first.db_config.clone(),
+                        profile: profile,
+                        db_path: first.db_path.clone(),
+                    });
                 }
-                _ => error!(target: "db","db:{} not found",db_name),
             }
         }
+        let db = DbRepository::open(&configs)?;
+        if let Some(first) = configs.first() {
+            write_db_names(&first.db_path, &configs)?;
+        }
+        Ok(DbRepository {
+            db: db,
+            configs: configs,
+        })
+    }
+    fn open(configs: &[RepositoryConfig]) -> Result<Option<DB>> {
+        let first = match configs.first() {
+            Some(config) => config,
+            None => return Ok(None),
+        };
+        if let Some(config) = configs.iter().find(|c| c.db_path != first.db_path) {
+            return Err(Error::OpenError {
+                name: config.db_name.clone(),
+                desc: format!(
+                    "db path {} differs from repository path {}; dbs kept in a directory of \
+                     their own must be imported once with DbRepository::import_legacy",
+                    config.db_path, first.db_path
+                ),
+            });
+        }
+        let columns = configs
+            .iter()
+            .map(|c| (c.db_name.clone(), c.profile.apply(&c.db_config)))
+            .collect::<Vec<_>>();
+        rockskvdb::Rockskvdb::open_with_columns(&first.db_config, &first.db_path, &columns)
+            .map(Some)
+            .map_err(|e| {
+                Error::OpenError {
+                    name: first.db_path.clone(),
+                    desc: e,
+                }
+            })
+    }
+    /// the rocksdb if it holds `db_name`.
+    fn db(&self, db_name: &str) -> Option<&DB> { self.db.as_ref().filter(|db| db.has_column(db_name)) }
+    /// flush overlay of every db to disk in one batch.
+    fn flush(&self) -> Result<()> {
+        match self.db {
+            Some(ref db) => {
+                db.flush().map_err(|e| {
+                    Error::FlushError {
+                        name: db_names(&self.configs),
+                        desc: e,
+                    }
+                })
+            }
+            None => Ok(()),
+        }
+    }
+    /// compact the keys in `[from, to]` of a db.
+    pub fn compact_range(&self, db_name: &str, from: &[u8], to: &[u8]) -> Result<()> {
+        self.compact_db(db_name, Some(from), Some(to))
+    }
+    /// compact every db completely, e.g. when `disable_auto_compactions` is set.
+    pub fn compact(&self) -> Result<()> {
+        for config in &self.configs {
+            self.compact_db(&config.db_name, None, None)?;
+        }
         Ok(())
     }
-    /// close all dbs
-    fn close_all(&mut self) {
-        self.db_priority.clear();
-        self.dbs.clear();
+    fn compact_db(&self, db_name: &str, from: Option<&[u8]>, to: Option<&[u8]>) -> Result<()> {
+        let db = match self.db {
+            Some(ref db) => db,
+            None => return Err(Error::Other("repository closed".into())),
+        };
+        if !self.configs.iter().any(|c| c.db_name == db_name) {
+            return Err(Error::NotFound(db_name.into()));
+        }
+        // buffered writes are compacted too.
+        self.flush()?;
+        db.compact_range_cf(db_name, from, to).map_err(|e| {
+            Error::FlushError {
+                name: db_name.into(),
+                desc: e,
+            }
+        })
+    }
+    /// write a consistent copy of all dbs, buffered writes included, to the new directory
+    /// `path`. The copy opens as a repository with the same dbs.
+    pub fn checkpoint(&self, path: &str) -> Result<()> {
+        let db = match self.db {
+            Some(ref db) => db,
+            None => return Err(Error::Other("repository closed".into())),
+        };
+        db.checkpoint(path).map_err(|e| {
+            Error::FlushError {
+                name: path.into(),
+                desc: e,
+            }
+        })?;
+        write_db_names(path, &self.configs)
+    }
+    /// back up all dbs of the running repository to the new directory `path`: a checkpoint
+    /// and a manifest with the db names and the checksum of every file.
+    pub fn backup(&self, path: &str) -> Result<()> {
+        self.checkpoint(path)?;
+        let db_names = self.configs.iter().map(|c| c.db_name.clone()).collect::<Vec<_>>();
+        backup::write_manifest(path, &db_names)
+    }
+    /// verify the backup at `backup_path` against its manifest, copy it to the path of
+    /// `configs` and open it. Every db in `configs` must be in the backup, and the path
+    /// must not hold a repository yet.
+    pub fn restore(backup_path: &str, configs: Vec<RepositoryConfig>) -> Result<DbRepository> {
+        let (db_names, files) = backup::verify_manifest(backup_path)?;
+        let path = match configs.first() {
+            Some(config) => config.db_path.clone(),
+            None => {
+                return Err(Error::OpenError {
+                    name: backup_path.into(),
+                    desc: "no db to restore".into(),
+                })
+            }
+        };
+        if let Some(config) = configs.iter().find(|c| !db_names.contains(&c.db_name)) {
+            return Err(Error::OpenError {
+                name: config.db_name.clone(),
+                desc: format!("db is not in backup {}", backup_path),
+            });
+        }
+        let restore_error = |desc: String| {
+            Error::OpenError {
+                name: path.clone(),
+                desc: desc,
+            }
+        };
+        if fs::read_dir(&path).map(|mut dir| dir.next().is_some()).unwrap_or(false) {
+            return Err(restore_error("restore path is not empty".into()));
+        }
+        let created = !Path::new(&path).exists();
+        fs::create_dir_all(&path).map_err(|e| restore_error(e.to_string()))?;
+        let restored = files
+            .iter()
+            .try_for_each(|file| {
+                fs::copy(Path::new(backup_path).join(file), Path::new(&path).join(file))
+                    .map(|_| ())
+                    .map_err(|e| restore_error(format!("can not restore {}: {}", file, e)))
+            })
+            .and_then(|()| DbRepository::init(configs));
+        if restored.is_err() {
+            // leave the path as it was, so that the restore can be retried.
+            let _ = fs::remove_dir_all(&path);
+            if !created {
+                let _ = fs::create_dir(&path);
+            }
+        }
+        restored
+    }
+    /// import dbs that each live in a rocksdb directory of their own, as all dbs did before
+    /// they shared one rocksdb, into a new repository at `path`. `configs` are the old
+    /// configs, one directory per db; the repository is opened with them moved to `path`.
+    /// `path` must not hold a repository yet, and the old directories are left untouched.
+    pub fn import_legacy(configs: Vec<RepositoryConfig>, path: &str) -> Result<DbRepository> {
+        let import_error = |name: &str, desc: String| {
+            Error::OpenError {
+                name: name.into(),
+                desc: desc,
+            }
+        };
+        if fs::read_dir(path).map(|mut dir| dir.next().is_some()).unwrap_or(false) {
+            return Err(import_error(path, "import path is not empty".into()));
+        }
+        let legacy = configs.clone();
+        let configs = configs
+            .into_iter()
+            .map(|config| {
+                RepositoryConfig {
+                    db_path: path.into(),
+                    ..config
+                }
+            })
+            .collect::<Vec<_>>();
+        let repository = DbRepository::init(configs)?;
+        let imported = match repository.db {
+            Some(ref db) => {
+                legacy.iter().try_for_each(|config| {
+                    let old = rockskvdb::Rockskvdb::open(&config.db_config, &config.db_path)
+                        .map_err(|e| import_error(&config.db_path, e))?;
+                    let count = db
+                        .import_cf(&config.db_name, &old)
+                        .map_err(|e| import_error(&config.db_name, e))?;
+                    info!(target:"db", "imported {} keys of db {} from {}", count, config.db_name, config.db_path);
+                    Ok(())
+                })
+            }
+            None => Ok(()),
+        };
+        match imported {
+            Ok(()) => Ok(repository),
+            Err(e) => {
+                // leave nothing behind, so that the import can be retried.
+                drop(repository);
+                let _ = fs::remove_dir_all(path);
+                Err(e)
+            }
+        }
+    }
+    /// close the repository, repair the rocksdb files of all dbs and reopen.
+    pub fn repair(&mut self) -> Result<()> {
+        let first = match self.configs.first() {
+            Some(config) => config.clone(),
+            None => return Ok(()),
+        };
+        let _ = self.flush();
+        self.close_all();
+        let repaired = rockskvdb::Rockskvdb::repair(&first.db_config, &first.db_path).map_err(|e| {
+            Error::OpenError {
+                name: first.db_path.clone(),
+                desc: e,
+            }
+        });
+        self.db = DbRepository::open(&self.configs)?;
+        repaired
     }
+    /// statistics of every db by name, empty once closed.
+    pub fn stats(&self) -> HashMap<DbName, DbStats> {
+        match self.db {
+            Some(ref db) => {
+                self.configs
+                    .iter()
+                    .filter_map(|c| db.stats_cf(&c.db_name).map(|stats| (c.db_name.clone(), stats)))
+                    .collect()
+            }
+            None => HashMap::new(),
+        }
+    }
+    /// close all dbs
+    fn close_all(&mut self) { self.db = None; }
     /// reopen all dbs
-    fn open_all(&mut self) {
+    fn open_all(&mut self) -> Result<()> {
         self.close_all();
-        let configs = self.configs.clone();
-        for config in configs {
-            match rockskvdb::Rockskvdb::open(&config.db_config, &config.db_path) {
-                Ok(db) => {
-                    self.dbs.insert(config.db_name.clone(), RwLock::new(db));
-                    self.db_priority.push(config.db_name.clone());
-                }
-                Err(_e) => {}
+        self.db = DbRepository::open(&self.configs).map_err(|e| {
+            error!(target:"db", "can not reopen dbs {}: {}", db_names(&self.configs), e);
+            e
+        })?;
+        Ok(())
+    }
+}
+
+/// names of all dbs, for errors of writes that may span them.
+fn db_names(configs: &[RepositoryConfig]) -> String {
+    configs
+        .iter()
+        .map(|c| c.db_name.as_str())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// names and profiles of the dbs known to the repository at `path`, empty for a new
+/// repository.
+fn read_db_names(path: &str) -> Result<Vec<(String, DbProfile)>> {
+    let read_error = |desc: String| {
+        Error::OpenError {
+            name: path.into(),
+            desc: format!("can not read {}: {}", DB_NAMES_FILE, desc),
+        }
+    };
+    let names = match fs::read_to_string(Path::new(path).join(DB_NAMES_FILE)) {
+        Ok(names) => names,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(read_error(e.to_string())),
+    };
+    names
+        .lines()
+        .map(|line| {
+            let mut parts = line.split('\t');
+            let db_name = parts.next().unwrap_or_default().to_string();
+            let profile = match parts.next() {
+                Some(profile) => profile.parse().map_err(&read_error)?,
+                None => DbProfile::General,
             };
+            Ok((db_name, profile))
+        })
+        .collect()
+}
+
+/// replace the list of known dbs at the repository `path` by the names in `configs`.
+fn write_db_names(path: &str, configs: &[RepositoryConfig]) -> Result<()> {
+    let path = Path::new(path);
+    let names = configs
+        .iter()
+        .map(|c| format!("{}\t{}\n", c.db_name, c.profile.name()))
+        .collect::<String>();
+    let file = path.join(DB_NAMES_FILE);
+    let tmp = path.join(format!("{}.tmp", DB_NAMES_FILE));
+    fs::write(&tmp, names)
+        .and_then(|_| fs::rename(&tmp, &file))
+        .map_err(|e| {
+            Error::FlushError {
+                name: path.display().to_string(),
+                desc: format!("can not write {}: {}", DB_NAMES_FILE, e),
+            }
+        })
+}
+
+/// snapshot of the in-memory repositories: a copy of every db.
+struct MapSnapshot<D> {
+    dbs: HashMap<DbName, D>,
+}
+
+impl KeyValueDB for DbRepository {
+    fn get(&self, db_name: &str, key: &[u8]) -> Result<Option<DBValue>> {
+        match self.db(db_name) {
+            Some(db) => {
+                let res = db.get_cf(db_name, key);
+                trace!(target:"db", "db:{}, Get key = {:?}, value = {:?}", db_name, key, res);
+                Ok(res)
+            }
+            _ => Err(Error::NotFound(db_name.into())),
+        }
+    }
+
+    fn keys(&self) -> Option<Vec<String>> {
+        match self.db {
+            Some(_) => Some(self.configs.iter().map(|c| c.db_name.clone()).collect()),
+            None => Some(Vec::new()),
+        }
+    }
+
+    fn write(&self, transaction: DBTransaction) -> Result<()> {
+        match self.db {
+            Some(ref db) => {
+                db.write(transaction).map_err(|e| {
+                    Error::FlushError {
+                        name: db_names(&self.configs),
+                        desc: e,
+                    }
+                })
+            }
+            None => Err(Error::Other("repository closed".into())),
+        }
+    }
+
+    fn write_buffered(&self, transaction: DBTransaction) {
+        match self.db {
+            Some(ref db) => db.write_buffered(transaction),
+            None => error!(target:"db","repository closed"),
+        }
+    }
+
+    fn iter(&self, db_name: &str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        match self.db(db_name) {
+            Some(db) => db.iter_cf(db_name),
+            _ => {
+                error!(target:"db","db:{} not found",db_name);
+                Box::new(None.into_iter())
+            }
+        }
+    }
+
+    fn get_by_prefix(&self, db_name: &str, prefix: &[u8]) -> Option<Box<[u8]>> {
+        match self.db(db_name) {
+            Some(db) => db.get_by_prefix_cf(db_name, prefix),
+            None => {
+                error!(target:"db","db:{} not found",db_name);
+                None
+            }
+        }
+    }
+
+    fn iter_from_prefix<'a>(
+        &'a self,
+        db_name: &str,
+        prefix: &[u8],
+    ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>
+    {
+        match self.db(db_name) {
+            Some(db) => db.iter_from_prefix_cf(db_name, prefix),
+            None => {
+                error!(target:"db","db:{} not found",db_name);
+                Box::new(None.into_iter())
+            }
+        }
+    }
+
+    fn iter_range<'a>(
+        &'a self,
+        db_name: &str,
+        start: &[u8],
+        end: &[u8],
+    ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>
+    {
+        match self.db(db_name) {
+            Some(db) => db.iter_range_cf(db_name, start, end),
+            None => {
+                error!(target:"db","db:{} not found",db_name);
+                Box::new(None.into_iter())
+            }
+        }
+    }
+
+    fn iter_rev<'a>(&'a self, db_name: &str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+        match self.db(db_name) {
+            Some(db) => db.iter_rev_cf(db_name),
+            None => {
+                error!(target:"db","db:{} not found",db_name);
+                Box::new(None.into_iter())
+            }
+        }
+    }
+
+    fn iter_rev_from<'a>(
+        &'a self,
+        db_name: &str,
+        key: &[u8],
+    ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>
+    {
+        match self.db(db_name) {
+            Some(db) => db.iter_rev_from_cf(db_name, key),
+            None => {
+                error!(target:"db","db:{} not found",db_name);
+                Box::new(None.into_iter())
+            }
+        }
+    }
+
+    fn snapshot<'a>(&'a self) -> Box<KeyValueSnapshot + 'a> {
+        match self.db {
+            Some(ref db) => Box::new(db.snapshot()),
+            None => {
+                error!(target:"db","repository closed");
+                Box::new(MapSnapshot::<MockDb> {
+                    dbs: HashMap::new(),
+                })
+            }
+        }
+    }
+
+    fn flush(&self) -> Result<()> { DbRepository::flush(self) }
+
+    #[cfg(test)]
+    fn close_all(&mut self) { DbRepository::close_all(self); }
+    #[cfg(test)]
+    fn open_all(&mut self) -> Result<()> { DbRepository::open_all(self) }
+}
+
+impl<'a> KeyValueSnapshot for DBSnapshot<'a> {
+    fn get(&self, db_name: &str, key: &[u8]) -> Result<Option<DBValue>> {
+        match self.has_column(db_name) {
+            true => Ok(self.get_cf(db_name, key)),
+            false => Err(Error::NotFound(db_name.into())),
+        }
+    }
+
+    fn iter(&self, db_name: &str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        self.iter_cf(db_name)
+    }
+
+    fn iter_from_prefix(&self, db_name: &str, prefix: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        self.iter_from_prefix_cf(db_name, prefix)
+    }
+
+    fn iter_range(&self, db_name: &str, start: &[u8], end: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        self.iter_range_cf(db_name, start, end)
+    }
+
+    fn iter_rev_from(&self, db_name: &str, key: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        self.iter_rev_from_cf(db_name, key)
+    }
+}
+
+impl<D: KeyValueDAO> MapSnapshot<D> {
+    fn db(&self, db_name: &str) -> Option<&D> {
+        let db = self.dbs.get(db_name);
+        if db.is_none() {
+            error!(target:"db","db:{} not found",db_name);
+        }
+        db
+    }
+}
+
+impl<D: KeyValueDAO> KeyValueSnapshot for MapSnapshot<D> {
+    fn get(&self, db_name: &str, key: &[u8]) -> Result<Option<DBValue>> {
+        match self.dbs.get(db_name) {
+            Some(db) => Ok(db.get(key)),
+            None => Err(Error::NotFound(db_name.into())),
+        }
+    }
+
+    fn iter(&self, db_name: &str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        match self.db(db_name) {
+            Some(db) => db.iter(),
+            None => Box::new(None.into_iter()),
+        }
+    }
+
+    fn iter_from_prefix(&self, db_name: &str, prefix: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        match self.db(db_name) {
+            Some(db) => db.iter_from_prefix(prefix),
+            None => Box::new(None.into_iter()),
+        }
+    }
+
+    fn iter_range(&self, db_name: &str, start: &[u8], end: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        match self.db(db_name) {
+            Some(db) => db.iter_range(start, end),
+            None => Box::new(None.into_iter()),
+        }
+    }
+
+    fn iter_rev_from(&self, db_name: &str, key: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        match self.db(db_name) {
+            Some(db) => db.iter_rev_from(key),
+            None => Box::new(None.into_iter()),
         }
     }
 }
@@ -165,13 +704,14 @@ impl MockDbRepository {
     /// close all dbs
     fn close_all(&mut self) { self.dbs.clear(); }
     /// reopen all dbs
-    fn open_all(&mut self) {
+    fn open_all(&mut self) -> Result<()> {
         self.close_all();
         let configs = self.configs.clone();
         for db_name in configs {
             let mut db = mockkvdb::Mockkvdb::new_default();
             self.dbs.insert(db_name, RwLock::new(db));
         }
+        Ok(())
     }
 }
 
@@ -230,7 +770,7 @@ macro_rules! impl_keyvaluedb {
                 }
             }
 
-            fn iter(&self, db_name: &'static str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+            fn iter(&self, db_name: &str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
                 match self.dbs.get(db_name) {
                     Some(db) => {
                         let db = db.read();
@@ -243,7 +783,7 @@ macro_rules! impl_keyvaluedb {
                 }
             }
 
-            fn get_by_prefix(&self, db_name: &'static str, prefix: &[u8]) -> Option<Box<[u8]>> {
+            fn get_by_prefix(&self, db_name: &str, prefix: &[u8]) -> Option<Box<[u8]>> {
                 match self.dbs.get(db_name) {
                     Some(db) => {
                         let db = db.read();
@@ -260,7 +800,7 @@ macro_rules! impl_keyvaluedb {
             fn iter_from_prefix<'a>(
                 &'a self,
                 db_name: &str,
-                prefix: &'static [u8],
+                prefix: &[u8],
             ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>
             {
                 match self.dbs.get(db_name) {
@@ -276,15 +816,653 @@ macro_rules! impl_keyvaluedb {
                 }
             }
 
+            fn iter_range<'a>(
+                &'a self,
+                db_name: &str,
+                start: &[u8],
+                end: &[u8],
+            ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>
+            {
+                match self.dbs.get(db_name) {
+                    Some(db) => db.read().iter_range(start, end),
+                    None => {
+                        error!(target:"db","db:{} not found",db_name);
+                        Box::new(None.into_iter())
+                    }
+                }
+            }
+
+            fn iter_rev<'a>(&'a self, db_name: &str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a> {
+                match self.dbs.get(db_name) {
+                    Some(db) => db.read().iter_rev(),
+                    None => {
+                        error!(target:"db","db:{} not found",db_name);
+                        Box::new(None.into_iter())
+                    }
+                }
+            }
+
+            fn iter_rev_from<'a>(
+                &'a self,
+                db_name: &str,
+                key: &[u8],
+            ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>
+            {
+                match self.dbs.get(db_name) {
+                    Some(db) => db.read().iter_rev_from(key),
+                    None => {
+                        error!(target:"db","db:{} not found",db_name);
+                        Box::new(None.into_iter())
+                    }
+                }
+            }
+
+            fn snapshot<'a>(&'a self) -> Box<KeyValueSnapshot + 'a> {
+                // hold every db so the copies are taken at one point in time.
+                let dbs = self
+                    .dbs
+                    .iter()
+                    .map(|(name, db)| (name, db.read()))
+                    .collect::<Vec<_>>();
+                Box::new(MapSnapshot {
+                    dbs: dbs
+                        .iter()
+                        .map(|(name, db)| ((*name).clone(), (**db).clone()))
+                        .collect(),
+                })
+            }
+
             fn flush(&self) -> Result<()> { $name::flush(self) }
 
             #[cfg(test)]
             fn close_all(&mut self) { $name::close_all(self); }
             #[cfg(test)]
-            fn open_all(&mut self) { $name::open_all(self); }
+            fn open_all(&mut self) -> Result<()> { $name::open_all(self) }
         }
     };
 }
-impl_keyvaluedb!(DbRepository);
 impl_keyvaluedb!(MockDbRepository);
 impl_keyvaluedb!(MemoryDBRepository);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbconfigs::{DatabaseConfig, WriteMode};
+    use std::env;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    fn configs(path: &str, names: &[&str]) -> Vec<RepositoryConfig> {
+        names
+            .iter()
+            .map(|name| {
+                RepositoryConfig {
+                    db_name: name.to_string(),
+                    db_config: DatabaseConfig::default(),
+                    profile: DbProfile::General,
+                    db_path: path.into(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn multi_db_transaction() {
+        let path = "./temp/repository_multi_db";
+        {
+            let db = DbRepository::init(configs(path, &["headers", "bodies"])).unwrap();
+            let mut batch = DBTransaction::new();
+            batch.put("headers", b"1", b"header");
+            batch.put("bodies", b"1", b"body");
+            db.write(batch).unwrap();
+            assert_eq!(db.get("headers", b"1").unwrap().unwrap(), b"header".to_vec());
+            assert_eq!(db.get("bodies", b"1").unwrap().unwrap(), b"body".to_vec());
+            // the same key lives separately in each db.
+            let mut batch = DBTransaction::new();
+            batch.delete("headers", b"1");
+            db.write_buffered(batch);
+            assert_eq!(db.get("headers", b"1").unwrap(), None);
+            assert!(db.get("receipts", b"1").is_err());
+        }
+        {
+            let db = DbRepository::init(configs(path, &["headers", "bodies"])).unwrap();
+            assert_eq!(db.get("headers", b"1").unwrap(), None);
+            assert_eq!(db.get("bodies", b"1").unwrap().unwrap(), b"body".to_vec());
+            assert_eq!(db.iter("bodies").count(), 1);
+        }
+        let _ = fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn range_scans() {
+        let path = "./temp/repository_range_scans";
+        {
+            let db = DbRepository::init(configs(path, &["headers", "bodies"])).unwrap();
+            let mut batch = DBTransaction::new();
+            for number in (0..100u64).filter(|n| n % 10 == 0) {
+                let key = number.to_be_bytes();
+                batch.put("headers", &key, &key);
+                batch.put("bodies", &key, b"body");
+            }
+            db.write(batch).unwrap();
+
+            // latest block at or below 25.
+            let key = 25u64.to_be_bytes();
+            let (latest, _) = db.iter_rev_from("headers", &key).next().unwrap();
+            assert_eq!(&*latest, &20u64.to_be_bytes()[..]);
+            assert_eq!(&*db.last("headers").unwrap().0, &90u64.to_be_bytes()[..]);
+            assert_eq!(db.iter_rev("bodies").count(), 10);
+
+            // a page of blocks 30..60.
+            let page = db
+                .iter_range("headers", &30u64.to_be_bytes(), &60u64.to_be_bytes())
+                .map(|(k, _)| k.into_vec())
+                .collect::<Vec<_>>();
+            assert_eq!(page, vec![30u64.to_be_bytes().to_vec(), 40u64.to_be_bytes().to_vec(), 50u64.to_be_bytes().to_vec()]);
+
+            let prefix = vec![0u8; 7];
+            assert_eq!(db.iter_from_prefix("headers", &prefix).count(), 10);
+            assert_eq!(db.iter_range("receipts", &[], &[0xff]).count(), 0);
+        }
+        let _ = fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn dbs_share_one_path() {
+        let mut configs = configs("./temp/repository_one_path", &["headers", "bodies"]);
+        configs[1].db_path = "./temp/repository_other_path".into();
+        assert!(DbRepository::init(configs).is_err());
+        let _ = fs::remove_dir_all("./temp/repository_one_path");
+    }
+
+    const CRASH_PATH: &str = "DB_CRASH_TEST_PATH";
+    const CRASH_MODE: &str = "DB_CRASH_TEST_MODE";
+
+    /// Runs in the child process started by `crash`: commits a few transactions, reports
+    /// back and waits to be killed.
+    #[test]
+    #[ignore]
+    fn crash_child() {
+        let path = match env::var(CRASH_PATH) {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+        let mut configs = configs(&path, &["headers", "bodies"]);
+        if env::var(CRASH_MODE).ok() == Some("buffered".into()) {
+            for config in &mut configs {
+                config.db_config.write_mode = WriteMode::Buffered;
+            }
+        }
+        let db = DbRepository::init(configs).unwrap();
+        for i in 0..100u8 {
+            let mut batch = DBTransaction::new();
+            batch.put("headers", &[i], b"header");
+            batch.put("bodies", &[i], b"body");
+            db.write(batch).unwrap();
+        }
+        println!("committed");
+        loop {
+            ::std::thread::sleep(::std::time::Duration::from_secs(1));
+        }
+    }
+
+    /// Kill a child process writing in `mode` and return how many entries each db kept.
+    fn crash(path: &str, mode: &str) -> (usize, usize) {
+        let _ = fs::remove_dir_all(path);
+        let mut child = Command::new(env::current_exe().unwrap())
+            .args(["dbrepository::tests::crash_child", "--exact", "--ignored", "--nocapture"])
+            .env(CRASH_PATH, path)
+            .env(CRASH_MODE, mode)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        {
+            let stdout = BufReader::new(child.stdout.as_mut().unwrap());
+            let committed = stdout.lines().any(|line| line.unwrap().contains("committed"));
+            assert!(committed, "child exited before committing");
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let db = DbRepository::init(configs(path, &["headers", "bodies"])).unwrap();
+        let kept = (db.iter("headers").count(), db.iter("bodies").count());
+        drop(db);
+        let _ = fs::remove_dir_all(path);
+        kept
+    }
+
+    #[test]
+    fn durable_writes_survive_crash() {
+        assert_eq!(crash("./temp/repository_crash_durable", "durable"), (100, 100));
+    }
+
+    #[test]
+    fn buffered_writes_are_lost_on_crash() {
+        assert_eq!(crash("./temp/repository_crash_buffered", "buffered"), (0, 0));
+    }
+
+    #[test]
+    fn memory_repository() {
+        let db = MemoryDBRepository::init(vec!["headers".into(), "bodies".into()]);
+        let mut batch = DBTransaction::new();
+        batch.put("headers", b"2", b"two");
+        batch.put("headers", b"1", b"one");
+        batch.put("bodies", b"1", b"body");
+        db.write(batch).unwrap();
+        assert_eq!(db.get("headers", b"1").unwrap().unwrap(), b"one".to_vec());
+        assert_eq!(
+            db.iter("headers").map(|(k, _)| k.into_vec()).collect::<Vec<_>>(),
+            vec![b"1".to_vec(), b"2".to_vec()]
+        );
+
+        let mut batch = DBTransaction::new();
+        batch.delete("headers", b"1");
+        db.write(batch).unwrap();
+        assert_eq!(db.get("headers", b"1").unwrap(), None);
+        assert_eq!(db.get("bodies", b"1").unwrap().unwrap(), b"body".to_vec());
+        assert!(db.get("receipts", b"1").is_err());
+    }
+
+    #[test]
+    fn insert_and_drop_dbs() {
+        let path = "./temp/repository_insert_drop";
+        {
+            let mut db = DbRepository::init(configs(path, &["headers"])).unwrap();
+            db.insert_db(configs(path, &["index"])).unwrap();
+            assert!(db.insert_db(configs(path, &["index"])).is_err());
+            assert!(db.insert_db(configs("./temp/elsewhere", &["other"])).is_err());
+            let mut batch = DBTransaction::new();
+            batch.put("headers", b"1", b"header");
+            batch.put("index", b"1", b"entry");
+            db.write(batch).unwrap();
+            assert_eq!(db.get("index", b"1").unwrap().unwrap(), b"entry".to_vec());
+        }
+        {
+            // the inserted db is reopened without being configured.
+            let mut db = DbRepository::init(configs(path, &["headers"])).unwrap();
+            assert_eq!(db.keys().unwrap(), vec!["headers".to_string(), "index".to_string()]);
+            assert_eq!(db.get("index", b"1").unwrap().unwrap(), b"entry".to_vec());
+
+            let mut batch = DBTransaction::new();
+            batch.put("index", b"2", b"unflushed");
+            db.write_buffered(batch);
+            db.drop_db("index").unwrap();
+            assert!(db.get("index", b"1").is_err());
+            assert!(db.drop_db("index").is_err());
+            assert_eq!(db.keys().unwrap(), vec!["headers".to_string()]);
+        }
+        {
+            let mut db = DbRepository::init(configs(path, &["headers"])).unwrap();
+            assert_eq!(db.keys().unwrap(), vec!["headers".to_string()]);
+            assert_eq!(db.get("headers", b"1").unwrap().unwrap(), b"header".to_vec());
+            db.insert_db(configs(path, &["index"])).unwrap();
+            assert_eq!(db.iter("index").count(), 0);
+        }
+        let _ = fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn stats() {
+        let path = "./temp/repository_stats";
+        {
+            let db = DbRepository::init(configs(path, &["headers", "bodies"])).unwrap();
+            let mut batch = DBTransaction::new();
+            for i in 0..10u8 {
+                batch.put("headers", &[i], b"header");
+            }
+            batch.put("bodies", b"1", b"body");
+            db.write(batch).unwrap();
+            db.get("headers", &[1]).unwrap();
+            db.iter("headers").count();
+
+            let stats = db.stats();
+            assert_eq!(stats.len(), 2);
+            let headers = stats["headers"];
+            assert_eq!(headers.estimated_keys, 10);
+            assert!(headers.memtable_size > 0);
+            assert_eq!(headers.overlay_keys, 0);
+            assert_eq!((headers.reads, headers.writes), (2, 10));
+            let bodies = stats["bodies"];
+            assert_eq!((bodies.overlay_keys, bodies.overlay_size), (0, 0));
+            assert_eq!((bodies.reads, bodies.writes), (0, 1));
+
+            let mut batch = DBTransaction::new();
+            batch.put("bodies", b"2", b"body");
+            db.write_buffered(batch);
+            let bodies = db.stats()["bodies"];
+            assert_eq!((bodies.overlay_keys, bodies.overlay_size), (1, 5));
+            assert_eq!(bodies.metrics()[5], ("overlay_keys", 1));
+        }
+        let _ = fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn maintenance() {
+        let path = "./temp/repository_maintenance";
+        let copy = "./temp/repository_maintenance_checkpoint";
+        let _ = fs::remove_dir_all(copy);
+        {
+            let mut db = DbRepository::init(configs(path, &["headers", "bodies"])).unwrap();
+            let mut batch = DBTransaction::new();
+            for i in 0..100u8 {
+                batch.put("headers", &[i], b"header");
+                batch.put("bodies", &[i], b"body");
+            }
+            db.write(batch).unwrap();
+            let mut batch = DBTransaction::new();
+            for i in 0..50u8 {
+                batch.delete("headers", &[i]);
+            }
+            db.write_buffered(batch);
+
+            db.compact_range("headers", &[0], &[49]).unwrap();
+            assert!(db.stats()["headers"].sst_size > 0);
+            assert_eq!(db.stats()["headers"].overlay_keys, 0);
+            db.compact().unwrap();
+            assert!(db.compact_range("receipts", &[0], &[1]).is_err());
+            assert_eq!(db.iter("headers").count(), 50);
+
+            let mut batch = DBTransaction::new();
+            batch.put("bodies", b"buffered", b"body");
+            db.write_buffered(batch);
+            db.checkpoint(copy).unwrap();
+            assert!(db.checkpoint(copy).is_err());
+
+            db.repair().unwrap();
+            assert_eq!(db.iter("headers").count(), 50);
+            assert_eq!(db.iter("bodies").count(), 101);
+        }
+        {
+            // the checkpoint knows its dbs.
+            let db = DbRepository::init(configs(copy, &["headers"])).unwrap();
+            assert_eq!(db.iter("headers").count(), 50);
+            assert_eq!(db.get("bodies", b"buffered").unwrap().unwrap(), b"body".to_vec());
+        }
+        let _ = fs::remove_dir_all(path);
+        let _ = fs::remove_dir_all(copy);
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let path = "./temp/repository_backup";
+        let backup = "./temp/repository_backup_copy";
+        let restored = "./temp/repository_backup_restored";
+        let _ = fs::remove_dir_all(backup);
+        let _ = fs::remove_dir_all(restored);
+        {
+            let db = DbRepository::init(configs(path, &["headers", "bodies"])).unwrap();
+            let mut batch = DBTransaction::new();
+            batch.put("headers", b"1", b"header");
+            batch.put("bodies", b"1", b"body");
+            db.write(batch).unwrap();
+            let mut batch = DBTransaction::new();
+            batch.put("bodies", b"2", b"buffered");
+            db.write_buffered(batch);
+            db.backup(backup).unwrap();
+
+            let mut batch = DBTransaction::new();
+            batch.put("headers", b"2", b"after backup");
+            db.write(batch).unwrap();
+        }
+        assert!(DbRepository::restore(backup, configs(restored, &["headers", "receipts"])).is_err());
+        {
+            let db = DbRepository::restore(backup, configs(restored, &["headers", "bodies"])).unwrap();
+            assert_eq!(db.get("headers", b"1").unwrap().unwrap(), b"header".to_vec());
+            assert_eq!(db.get("headers", b"2").unwrap(), None);
+            assert_eq!(db.get("bodies", b"2").unwrap().unwrap(), b"buffered".to_vec());
+        }
+        // never restored over an existing repository.
+        assert!(DbRepository::restore(backup, configs(restored, &["headers"])).is_err());
+        let _ = fs::remove_dir_all(restored);
+
+        // a changed file fails the manifest.
+        let sst = fs::read_dir(backup)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|file| file.extension() == Some("sst".as_ref()))
+            .unwrap();
+        let mut data = fs::read(&sst).unwrap();
+        data[0] ^= 1;
+        fs::write(&sst, data).unwrap();
+        match DbRepository::restore(backup, configs(restored, &["headers"])) {
+            Err(Error::OpenError {
+                desc, ..
+            }) => assert!(desc.starts_with("checksum mismatch")),
+            _ => panic!("corrupted backup restored"),
+        }
+        let _ = fs::remove_dir_all(path);
+        let _ = fs::remove_dir_all(backup);
+        let _ = fs::remove_dir_all(restored);
+    }
+
+    #[test]
+    fn restore_tampered_manifest() {
+        let path = "./temp/repository_tampered";
+        let backup = "./temp/repository_tampered_copy";
+        let restored = "./temp/repository_tampered_restored";
+        let outside = "./temp/repository_tampered_outside";
+        let _ = fs::remove_dir_all(backup);
+        let _ = fs::remove_dir_all(restored);
+        DbRepository::init(configs(path, &["headers"]))
+            .unwrap()
+            .backup(backup)
+            .unwrap();
+        fs::write(outside, b"outside").unwrap();
+        let hash = ::blake2b::blake2b_buffer(&mut BufReader::new(fs::File::open(outside).unwrap())).unwrap();
+        let manifest = Path::new(backup).join(backup::MANIFEST_FILE);
+        let original = fs::read_to_string(&manifest).unwrap();
+
+        for file in &["../repository_tampered_outside", "/etc/hostname", ".", "BACKUP_MANIFEST"] {
+            fs::write(&manifest, format!("{}file {:x} {}\n", original, hash, file)).unwrap();
+            match DbRepository::restore(backup, configs(restored, &["headers"])) {
+                Err(Error::OpenError {
+                    desc, ..
+                }) => assert_eq!(desc, format!("invalid backup file {}", file)),
+                _ => panic!("tampered backup restored"),
+            }
+        }
+        assert!(!Path::new(restored).exists());
+        fs::write(&manifest, original).unwrap();
+        assert!(DbRepository::restore(backup, configs(restored, &["headers"])).is_ok());
+
+        let _ = fs::remove_dir_all(path);
+        let _ = fs::remove_dir_all(backup);
+        let _ = fs::remove_dir_all(restored);
+        let _ = fs::remove_file(outside);
+    }
+
+    #[test]
+    fn reopen_error() {
+        let path = "./temp/repository_reopen";
+        let mut db = DbRepository::init(configs(path, &["headers"])).unwrap();
+        db.close_all();
+        {
+            // the lock is held by another repository.
+            let _other = DbRepository::init(configs(path, &["headers"])).unwrap();
+            assert!(db.open_all().is_err());
+        }
+        db.open_all().unwrap();
+        assert_eq!(db.get("headers", b"1").unwrap(), None);
+        drop(db);
+        let _ = fs::remove_dir_all(path);
+    }
+
+    #[test]
+    fn restore_retry() {
+        let path = "./temp/repository_restore_retry";
+        let backup = "./temp/repository_restore_retry_copy";
+        let restored = "./temp/repository_restore_retry_restored";
+        let _ = fs::remove_dir_all(backup);
+        let _ = fs::remove_dir_all(restored);
+        DbRepository::init(configs(path, &["headers", "bodies"]))
+            .unwrap()
+            .backup(backup)
+            .unwrap();
+
+        // the second db is not at the path of the first, so opening the restored copy fails.
+        let mut mismatched = configs(restored, &["headers", "bodies"]);
+        mismatched[1].db_path = path.into();
+        assert!(DbRepository::restore(backup, mismatched.clone()).is_err());
+        assert!(!Path::new(restored).exists());
+        // an empty directory that was already there stays.
+        fs::create_dir_all(restored).unwrap();
+        assert!(DbRepository::restore(backup, mismatched).is_err());
+        assert_eq!(fs::read_dir(restored).unwrap().count(), 0);
+        assert!(DbRepository::restore(backup, configs(restored, &["headers", "bodies"])).is_ok());
+
+        let _ = fs::remove_dir_all(path);
+        let _ = fs::remove_dir_all(backup);
+        let _ = fs::remove_dir_all(restored);
+    }
+
+    #[test]
+    fn import_legacy() {
+        let path = "./temp/repository_import";
+        let legacy = vec![
+            configs("./temp/repository_import_headers", &["headers"]).remove(0),
+            configs("./temp/repository_import_bodies", &["bodies"]).remove(0),
+        ];
+        for config in &legacy {
+            let db = rockskvdb::Rockskvdb::open(&config.db_config, &config.db_path).unwrap();
+            let mut batch = DBTransaction::new();
+            for i in 0..20u8 {
+                batch.put("default", &[i], config.db_name.as_bytes());
+            }
+            db.write(batch).unwrap();
+            db.flush().unwrap();
+        }
+        match DbRepository::init(legacy.clone()) {
+            Err(Error::OpenError {
+                desc, ..
+            }) => assert!(desc.contains("DbRepository::import_legacy")),
+            _ => panic!("separate db paths opened"),
+        }
+        {
+            let db = DbRepository::import_legacy(legacy.clone(), path).unwrap();
+            assert_eq!(db.iter("headers").count(), 20);
+            assert_eq!(db.get("bodies", &[7]).unwrap().unwrap(), b"bodies".to_vec());
+        }
+        assert!(DbRepository::import_legacy(legacy.clone(), path).is_err());
+        {
+            let db = DbRepository::init(configs(path, &["headers", "bodies"])).unwrap();
+            assert_eq!(db.get("headers", &[19]).unwrap().unwrap(), b"headers".to_vec());
+        }
+        let _ = fs::remove_dir_all(path);
+        for config in &legacy {
+            let _ = fs::remove_dir_all(&config.db_path);
+        }
+    }
+
+    #[test]
+    fn profiles() {
+        let path = "./temp/repository_profiles";
+        {
+            let mut configs = configs(path, &["state", "blocks"]);
+            configs[0].profile = "point_lookup".parse().unwrap();
+            configs[1].profile = DbProfile::AppendOnly;
+            let mut db = DbRepository::init(configs).unwrap();
+            db.insert_db(vec![RepositoryConfig {
+                db_name: "meta".into(),
+                db_config: DatabaseConfig::default(),
+                profile: DbProfile::Metadata,
+                db_path: path.into(),
+            }])
+            .unwrap();
+
+            let mut batch = DBTransaction::new();
+            for i in 0..20u8 {
+                batch.put("state", &[i; 32], &[i]);
+                batch.put("blocks", &[i], &[i]);
+            }
+            batch.put("state", b"latest", b"era");
+            batch.put("meta", b"best", b"1");
+            db.write(batch).unwrap();
+            db.compact().unwrap();
+
+            assert_eq!(db.get("state", &[3; 32]).unwrap().unwrap(), vec![3]);
+            assert_eq!(db.get("state", &[30; 32]).unwrap(), None);
+            // scans are not confined to the prefix of the seek key.
+            assert_eq!(db.iter("state").count(), 21);
+            assert_eq!(db.iter_from_prefix("state", &[3]).count(), 1);
+            assert_eq!(db.iter_range("state", &[3], &[6]).count(), 3);
+            assert_eq!(&*db.iter_rev_from("state", &[5; 33]).next().unwrap().0, &[5; 32][..]);
+            assert_eq!(&*db.last("state").unwrap().0, b"latest");
+            assert_eq!(db.iter_range("blocks", &[5], &[15]).count(), 10);
+        }
+        {
+            // profiles of dbs inserted at runtime are kept.
+            let db = DbRepository::init(configs(path, &["state", "blocks"])).unwrap();
+            assert_eq!(db.configs[2].profile, DbProfile::Metadata);
+            assert_eq!(db.get("meta", b"best").unwrap().unwrap(), b"1".to_vec());
+        }
+        for profile in &[DbProfile::General, DbProfile::PointLookup, DbProfile::AppendOnly, DbProfile::Metadata] {
+            assert_eq!(profile.name().parse::<DbProfile>().unwrap(), *profile);
+        }
+        assert!("state".parse::<DbProfile>().is_err());
+        let _ = fs::remove_dir_all(path);
+    }
+
+    // writes after the snapshot, buffered or not, stay invisible through it.
+    fn check_snapshot(db: &KeyValueDB) {
+        let mut batch = DBTransaction::new();
+        batch.put("headers", b"1", b"one");
+        batch.put("headers", b"2", b"two");
+        batch.put("bodies", b"1", b"body");
+        db.write(batch).unwrap();
+        let mut batch = DBTransaction::new();
+        batch.put("headers", b"3", b"three");
+        db.write_buffered(batch);
+
+        let snapshot = db.snapshot();
+        let mut batch = DBTransaction::new();
+        batch.delete("headers", b"1");
+        batch.put("headers", b"2", b"changed");
+        batch.put("headers", b"4", b"four");
+        batch.delete("bodies", b"1");
+        db.write(batch).unwrap();
+        let mut batch = DBTransaction::new();
+        batch.put("headers", b"5", b"five");
+        db.write_buffered(batch);
+        db.flush().unwrap();
+
+        assert_eq!(snapshot.get("headers", b"1").unwrap().unwrap(), b"one".to_vec());
+        assert_eq!(snapshot.get("headers", b"2").unwrap().unwrap(), b"two".to_vec());
+        assert_eq!(snapshot.get("headers", b"4").unwrap(), None);
+        assert_eq!(snapshot.get("bodies", b"1").unwrap().unwrap(), b"body".to_vec());
+        assert!(snapshot.get("receipts", b"1").is_err());
+        assert_eq!(
+            snapshot.iter("headers").collect::<Vec<_>>(),
+            vec![
+                (b"1".to_vec().into_boxed_slice(), b"one".to_vec().into_boxed_slice()),
+                (b"2".to_vec().into_boxed_slice(), b"two".to_vec().into_boxed_slice()),
+                (b"3".to_vec().into_boxed_slice(), b"three".to_vec().into_boxed_slice()),
+            ]
+        );
+        assert_eq!(snapshot.iter_from_prefix("headers", b"3").count(), 1);
+        assert_eq!(snapshot.iter_range("headers", b"2", b"9").count(), 2);
+        assert_eq!(&*snapshot.iter_rev_from("headers", b"9").next().unwrap().0, b"3");
+
+        // the db itself moved on.
+        assert_eq!(db.get("headers", b"1").unwrap(), None);
+        assert_eq!(db.iter("headers").count(), 4);
+    }
+
+    #[test]
+    fn snapshots() {
+        for &mode in &[WriteMode::Durable, WriteMode::Buffered] {
+            let path = format!("./temp/repository_snapshot_{:?}", mode);
+            {
+                let mut configs = configs(&path, &["headers", "bodies"]);
+                for config in &mut configs {
+                    config.db_config.write_mode = mode;
+                }
+                let db = DbRepository::init(configs).unwrap();
+                check_snapshot(&db);
+            }
+            let _ = fs::remove_dir_all(&path);
+        }
+        check_snapshot(&MockDbRepository::init(vec!["headers".into(), "bodies".into()]));
+        check_snapshot(&MemoryDBRepository::init(vec!["headers".into(), "bodies".into()]));
+    }
+}
@@ -0,0 +1,61 @@
+/*******************************************************************************
+ * Copyright (c) 2018-2019 Aion foundation.
+ *
+ *     This file is part of the aion network project.
+ *
+ *     The aion network project is free software: you can redistribute it
+ *     and/or modify it under the terms of the GNU General Public License
+ *     as published by the Free Software Foundation, either version 3 of
+ *     the License, or any later version.
+ *
+ *     The aion network project is distributed in the hope that it will
+ *     be useful, but WITHOUT ANY WARRANTY; without even the implied
+ *     warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ *     See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License
+ *     along with the aion network project source files.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ ******************************************************************************/
+
+/// Statistics of a single db: rocksdb properties of its column family, the writes
+/// waiting in memory and the operations served since it was opened.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DbStats {
+    /// Estimated number of keys on disk and in the memtables.
+    pub estimated_keys: u64,
+    /// Total size of the sst files (bytes).
+    pub sst_size: u64,
+    /// Size of the active and unflushed immutable memtables (bytes).
+    pub memtable_size: u64,
+    /// Bytes compaction has to rewrite to bring every level under its target size.
+    pub pending_compaction_bytes: u64,
+    /// Memory used by the block cache (bytes).
+    pub block_cache_usage: u64,
+    /// Keys written in buffered mode and not flushed yet.
+    pub overlay_keys: u64,
+    /// Size of the keys and values not flushed yet (bytes).
+    pub overlay_size: u64,
+    /// Point lookups and scans.
+    pub reads: u64,
+    /// Inserted or deleted keys.
+    pub writes: u64,
+}
+
+impl DbStats {
+    /// Named values, for export to monitoring.
+    pub fn metrics(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("estimated_keys", self.estimated_keys),
+            ("sst_size", self.sst_size),
+            ("memtable_size", self.memtable_size),
+            ("pending_compaction_bytes", self.pending_compaction_bytes),
+            ("block_cache_usage", self.block_cache_usage),
+            ("overlay_keys", self.overlay_keys),
+            ("overlay_size", self.overlay_size),
+            ("reads", self.reads),
+            ("writes", self.writes),
+        ]
+    }
+}
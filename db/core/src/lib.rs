@@ -33,7 +33,6 @@ extern crate regex;
 extern crate blake2b;
 extern crate rlp;
 extern crate parking_lot;
-extern crate interleaved_ordered;
 extern crate heapsize;
 extern crate plain_hasher;
 extern crate rand;
@@ -45,16 +44,22 @@ mod memorydb;
 mod traits;
 mod error;
 mod dbconfigs;
+mod dbstats;
+mod backup;
 
 use elastic_array::{ElasticArray32, ElasticArray128};
 pub use dbrepository::{DbRepository, MockDbRepository, MemoryDBRepository};
 pub use dbtransaction::{DBOp, DBTransaction};
 pub use mockkvdb::Mockkvdb;
 pub use rockskvdb::Rockskvdb;
-pub use traits::{HashStore, AsHashStore, KeyValueDB};
+pub use traits::{HashStore, AsHashStore, KeyValueDB, KeyValueSnapshot};
 pub use memorydb::MemoryDB;
 pub use error::Error;
-pub use dbconfigs::{DatabaseConfig, CompactionProfile, RepositoryConfig};
+pub use dbconfigs::{
+    DatabaseConfig, CompactionProfile, RepositoryConfig, WriteMode, CompressionConfig, CompressionType,
+    ZstdDictionary, DbProfile,
+};
+pub use dbstats::DbStats;
 
 pub type Key = ElasticArray32<u8>;
 pub type DBValue = ElasticArray128<u8>;
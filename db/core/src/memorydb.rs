@@ -20,13 +20,16 @@
  *
  ******************************************************************************/
 
-//! Reference-counted memory-based `HashStore` implementation.
+//! Reference-counted memory-based `HashStore` implementation,
+//! which also serves as an ordered in-memory key-value store.
 use std::mem;
-use std::collections::HashMap;
+use std::collections::Bound::{Excluded, Included, Unbounded};
+use std::collections::{BTreeMap, HashMap};
 use std::collections::hash_map::Entry;
 use heapsize::HeapSizeOf;
 use aion_types::H256;
-use super::{HashStore, DBValue};
+use super::{HashStore, DBValue, Key};
+use traits::{entries, KeyValueDAO};
 use blake2b::{BLAKE2B_NULL_RLP, blake2b};
 use plain_hasher::H256FastMap;
 use rlp::NULL_RLP;
@@ -34,6 +37,8 @@ use rlp::NULL_RLP;
 #[derive(Default, Clone, PartialEq)]
 pub struct MemoryDB {
     data: H256FastMap<(DBValue, i32)>,
+    /// key-value entries, ordered by key.
+    kv: BTreeMap<Key, DBValue>,
 }
 
 impl MemoryDB {
@@ -41,30 +46,15 @@ impl MemoryDB {
     pub fn new() -> MemoryDB {
         MemoryDB {
             data: H256FastMap::default(),
+            kv: BTreeMap::new(),
         }
     }
 
-    pub fn get(&self, k: &[u8]) -> Option<DBValue> {
-        match self.data.get(&H256::from(k)).cloned() {
-            Some(value) => Some(value.0),
-            _ => None,
-        }
-    }
-
-    pub fn delete(&mut self, _k: &[u8]) -> Option<DBValue> { unimplemented!() }
-
-    pub fn put(&mut self, _k: &[u8], _v: &DBValue) -> Option<DBValue> { unimplemented!() }
-
-    pub fn iter(&self) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> { unimplemented!() }
-
-    pub fn get_by_prefix(&self, _prefix: &[u8]) -> Option<Box<[u8]>> { unimplemented!() }
-
-    pub fn iter_from_prefix(&self, _prefix: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
-        unimplemented!()
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.kv.clear();
     }
 
-    pub fn clear(&mut self) { self.data.clear(); }
-
     /// Purge all zero-referenced data from the database.
     pub fn purge(&mut self) { self.data.retain(|_, &mut (_, rc)| rc != 0); }
 
@@ -129,6 +119,48 @@ impl MemoryDB {
     }
 }
 
+impl KeyValueDAO for MemoryDB {
+    fn get(&self, k: &[u8]) -> Option<DBValue> { self.kv.get(k).cloned() }
+
+    fn put(&mut self, k: &[u8], v: &DBValue) -> Option<DBValue> {
+        self.kv.insert(Key::from_slice(k), v.clone());
+        Some(v.clone())
+    }
+
+    fn delete(&mut self, k: &[u8]) -> Option<DBValue> { self.kv.remove(k) }
+
+    fn iter(&self) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> { entries(self.kv.iter()) }
+
+    fn get_by_prefix(&self, prefix: &[u8]) -> Option<Box<[u8]>> {
+        self.kv
+            .range::<[u8], _>((Included(prefix), Unbounded))
+            .next()
+            .filter(|&(k, _)| k.starts_with(prefix))
+            .map(|(_, v)| v.to_vec().into_boxed_slice())
+    }
+
+    fn iter_from_prefix(&self, prefix: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        entries(
+            self.kv
+                .range::<[u8], _>((Included(prefix), Unbounded))
+                .take_while(|&(k, _)| k.starts_with(prefix)),
+        )
+    }
+
+    fn iter_range(&self, start: &[u8], end: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        if start > end {
+            return Box::new(None.into_iter());
+        }
+        entries(self.kv.range::<[u8], _>((Included(start), Excluded(end))))
+    }
+
+    fn iter_rev(&self) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> { entries(self.kv.iter().rev()) }
+
+    fn iter_rev_from(&self, key: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        entries(self.kv.range::<[u8], _>((Unbounded, Included(key))).rev())
+    }
+}
+
 impl HashStore for MemoryDB {
     fn get(&self, key: &H256) -> Option<DBValue> {
         if key == &BLAKE2B_NULL_RLP {
@@ -278,4 +310,27 @@ mod tests {
             &(DBValue::from_slice(b"negative"), -2)
         );
     }
+
+    #[test]
+    fn key_value_entries() {
+        let mut m = MemoryDB::new();
+        let hash = m.insert(b"hashed");
+        m.put(b"b1", &DBValue::from_slice(b"1"));
+        m.put(b"a1", &DBValue::from_slice(b"2"));
+        m.put(b"b2", &DBValue::from_slice(b"3"));
+        assert_eq!(&*KeyValueDAO::get(&m, b"a1").unwrap(), b"2");
+        // hashed entries live in a namespace of their own, as in rocksdb.
+        assert_eq!(KeyValueDAO::get(&m, &hash), None);
+        assert_eq!(&*HashStore::get(&m, &hash).unwrap(), b"hashed");
+        m.remove(&hash);
+        assert_eq!(HashStore::get(&m, &hash), None);
+        assert_eq!(
+            m.iter().map(|(k, _)| k.into_vec()).collect::<Vec<_>>(),
+            vec![b"a1".to_vec(), b"b1".to_vec(), b"b2".to_vec()]
+        );
+        assert_eq!(&*m.get_by_prefix(b"b").unwrap(), b"1");
+        assert_eq!(m.get_by_prefix(b"c"), None);
+        assert_eq!(m.delete(b"b1").unwrap(), DBValue::from_slice(b"1"));
+        assert_eq!(KeyValueDAO::get(&m, b"b1"), None);
+    }
 }
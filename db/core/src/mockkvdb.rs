@@ -19,11 +19,13 @@
  *
  ******************************************************************************/
 
-use traits::KeyValueDAO;
+use traits::{entries, KeyValueDAO};
 use std::collections::BTreeMap;
+use std::collections::Bound::{Excluded, Included, Unbounded};
 use super::{Key,DBValue};
 
 /// Rocksdb mock instance in memory
+#[derive(Clone)]
 pub struct Mockkvdb {
     db: BTreeMap<Key, DBValue>,
 }
@@ -58,41 +60,36 @@ impl KeyValueDAO for Mockkvdb {
         self.db.remove(&ekey)
     }
 
-    fn iter(&self) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
-        Box::new(self.db.clone().into_iter().map(|(k, v)| {
-            (
-                k.into_vec().into_boxed_slice(),
-                v.into_vec().into_boxed_slice(),
-            )
-        }))
-    }
+    fn iter(&self) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> { entries(self.db.iter()) }
 
     fn get_by_prefix(&self, prefix: &[u8]) -> Option<Box<[u8]>> {
         self.db
-            .clone()
-            .iter()
-            .find(|&(ref k, _)| k.starts_with(prefix))
+            .range::<[u8], _>((Included(prefix), Unbounded))
+            .next()
+            .filter(|&(k, _)| k.starts_with(prefix))
             .map(|(_, v)| v.clone().into_vec().into_boxed_slice())
     }
 
-    fn iter_from_prefix(
-        &self,
-        prefix: &'static [u8],
-    ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>
-    {
-        Box::new(
+    fn iter_from_prefix(&self, prefix: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        entries(
             self.db
-                .clone()
-                .into_iter()
-                .skip_while(move |(k, _)| !k.starts_with(prefix))
-                .map(|(k, v)| {
-                    (
-                        k.into_vec().into_boxed_slice(),
-                        v.into_vec().into_boxed_slice(),
-                    )
-                }),
+                .range::<[u8], _>((Included(prefix), Unbounded))
+                .take_while(|&(k, _)| k.starts_with(prefix)),
         )
     }
+
+    fn iter_range(&self, start: &[u8], end: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        if start > end {
+            return Box::new(None.into_iter());
+        }
+        entries(self.db.range::<[u8], _>((Included(start), Excluded(end))))
+    }
+
+    fn iter_rev(&self) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> { entries(self.db.iter().rev()) }
+
+    fn iter_rev_from(&self, key: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
+        entries(self.db.range::<[u8], _>((Unbounded, Included(key))).rev())
+    }
 }
 
 #[cfg(test)]
@@ -19,31 +19,101 @@
  *
  ******************************************************************************/
 
-use std::cmp;
+use std::cmp::{self, Ordering};
+use std::iter::Peekable;
 use traits::KeyValueDAO;
 use parity_rocksdb::{
     DB, Options, BlockBasedOptions, Cache, ReadOptions, IteratorMode, Direction, WriteOptions, WriteBatch, DBIterator, Writable, DBCompactionStyle
 };
+use parity_rocksdb::rocksdb::Snapshot;
+use parking_lot::RwLock;
 use super::{Key, DBValue};
 use std::collections::HashMap;
-use interleaved_ordered::{interleave_ordered, InterleaveOrdered};
-use std::marker::PhantomData;
-use dbconfigs::DatabaseConfig;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use dbconfigs::{CompressionType, DatabaseConfig, WriteMode};
+use dbstats::DbStats;
+use dbtransaction::{DBTransaction, DBOp};
 
+/// Column family every rocksdb has, used by the `KeyValueDAO` operations.
+pub const DEFAULT_COLUMN: &str = "default";
+
+/// Flush the overlay once it holds more entries than this.
+const OVERLAY_FLUSH_THRESHOLD: usize = 10000;
+
+#[derive(Clone)]
 enum KeyState {
     Insert(DBValue),
     Delete,
 }
 
-pub struct RockskvdbIterator<'a> {
-    iter: InterleaveOrdered<::std::vec::IntoIter<(Box<[u8]>, Box<[u8]>)>, DBIterator>,
-    _marker: PhantomData<&'a Rockskvdb>,
+type Overlay = HashMap<Key, KeyState>;
+
+/// Operations served by a column since it was opened.
+#[derive(Default)]
+struct Counters {
+    reads: AtomicUsize,
+    writes: AtomicUsize,
 }
 
-impl<'a> Iterator for RockskvdbIterator<'a> {
+type KeyValueIter = Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+
+/// Keys visited by an iteration.
+enum Scan<'a> {
+    /// keys starting with the prefix, ascending.
+    Prefix(&'a [u8]),
+    /// keys in `start..end`, ascending.
+    Range(&'a [u8], &'a [u8]),
+    /// keys not greater than the given one, or all keys, descending.
+    Reverse(Option<&'a [u8]>),
+}
+
+impl<'a> Scan<'a> {
+    fn contains(&self, key: &[u8]) -> bool {
+        match *self {
+            Scan::Prefix(prefix) => key.starts_with(prefix),
+            Scan::Range(start, end) => key >= start && key < end,
+            Scan::Reverse(Some(last)) => key <= last,
+            Scan::Reverse(None) => true,
+        }
+    }
+}
+
+/// Sorted unflushed changes merged over a rocksdb iterator, the changes taking precedence.
+pub struct RockskvdbIterator {
+    overlay: Peekable<::std::vec::IntoIter<(Box<[u8]>, Option<Box<[u8]>>)>>,
+    db: Peekable<KeyValueIter>,
+    reverse: bool,
+}
+
+impl Iterator for RockskvdbIterator {
     type Item = (Box<[u8]>, Box<[u8]>);
 
-    fn next(&mut self) -> Option<Self::Item> { self.iter.next() }
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let ordering = match (self.overlay.peek(), self.db.peek()) {
+                (Some((overlay_key, _)), Some((db_key, _))) => {
+                    match self.reverse {
+                        true => db_key.cmp(overlay_key),
+                        false => overlay_key.cmp(db_key),
+                    }
+                }
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => return None,
+            };
+            match ordering {
+                Ordering::Greater => return self.db.next(),
+                Ordering::Equal => {
+                    self.db.next();
+                }
+                Ordering::Less => {}
+            }
+            // a deleted key is skipped.
+            if let Some((key, Some(value))) = self.overlay.next() {
+                return Some((key, value));
+            }
+        }
+    }
 }
 
 pub struct Rockskvdb {
@@ -51,7 +121,15 @@ pub struct Rockskvdb {
     write_options: WriteOptions,
     read_options: ReadOptions,
     block_cache_options: BlockBasedOptions,
-    overlay: HashMap<Key, KeyState>,
+    /// block cache shared by the columns without a cache share.
+    block_cache: Cache,
+    write_mode: WriteMode,
+    /// column families opened besides the default one.
+    columns: Vec<String>,
+    /// unflushed writes by column.
+    overlay: RwLock<HashMap<String, Overlay>>,
+    /// reads and writes by column, the default one included.
+    counters: HashMap<String, Counters>,
 }
 impl Rockskvdb {
     /// Crate a new database file by default.
@@ -61,79 +139,488 @@ impl Rockskvdb {
             write_options: WriteOptions::new(),
             read_options: ReadOptions::new(),
             block_cache_options: BlockBasedOptions::new(),
-            overlay: HashMap::new(),
+            block_cache: Cache::new(DatabaseConfig::default().memory_budget),
+            write_mode: WriteMode::Durable,
+            columns: Vec::new(),
+            overlay: RwLock::new(HashMap::new()),
+            counters: Rockskvdb::counters(&[]),
         }
     }
 
     /// Open database file. Creates if it does not exist.
     pub fn open(config: &DatabaseConfig, path: &str) -> Result<Self, String> {
-        let mut block_opts = BlockBasedOptions::new();
+        Rockskvdb::open_with_columns(config, path, &[])
+    }
 
-        {
-            block_opts.set_block_size(config.block_size);
-            let cache_size = cmp::max(8, config.memory_budget);
-            let cache = Cache::new(cache_size);
-            block_opts.set_cache(cache);
-        }
+    /// Open database file with a column family for each of `columns`, tuned by its own config.
+    /// Creates the database and missing column families if they do not exist.
+    pub fn open_with_columns(
+        config: &DatabaseConfig,
+        path: &str,
+        columns: &[(String, DatabaseConfig)],
+    ) -> Result<Self, String>
+    {
+        // one block cache for the columns without a cache share, sized by the budget of the db.
+        let block_cache = Cache::new(cmp::max(8, config.memory_budget));
+        let block_opts = Rockskvdb::block_options(config, &block_cache);
 
         let mut read_opts = ReadOptions::new();
         read_opts.set_verify_checksums(false);
+        read_opts.set_total_order_seek(true);
 
         let mut write_opts = WriteOptions::new();
-        if !config.wal {
+        if config.write_mode == WriteMode::Buffered && !config.wal {
             write_opts.disable_wal(true);
         }
 
-        match Rockskvdb::parse_options(&config, &block_opts) {
-            Ok(opts) => {
-                match DB::open(&opts, path) {
-                    Ok(t) => {
-                        Ok(Rockskvdb {
-                            db: t,
-                            write_options: write_opts,
-                            read_options: read_opts,
-                            block_cache_options: block_opts,
-                            overlay: HashMap::new(),
-                        })
-                    }
-                    Err(ref s)
-                        if s.starts_with("Corruption:") || s.starts_with(
-                            "Invalid argument: You have to open all column families",
-                        ) =>
-                    {
-                        warn!(target:"db","DB corrupted: {}, attempting repair", s);
-                        DB::repair(&opts, path)?;
-
-                        Ok(Rockskvdb {
-                            db: DB::open(&opts, path)?,
-                            write_options: write_opts,
-                            read_options: read_opts,
-                            block_cache_options: block_opts,
-                            overlay: HashMap::new(),
-                        })
-                    }
-                    Err(s) => return Err(s.into()),
+        let mut opts = Rockskvdb::parse_options(config, &block_opts)?;
+        opts.set_parsed_options("create_missing_column_families=true")?;
+        let names = columns
+            .iter()
+            .filter(|(name, _)| name != DEFAULT_COLUMN)
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        let mut cf_opts = Vec::with_capacity(names.len());
+        for (name, cf_config) in columns {
+            if name != DEFAULT_COLUMN {
+                let cf_block_opts = Rockskvdb::block_options(cf_config, &block_cache);
+                cf_opts.push(Rockskvdb::parse_options(cf_config, &cf_block_opts)?);
+            }
+        }
+
+        let open = |names: &[String]| {
+            match names.is_empty() {
+                true => DB::open(&opts, path),
+                false => {
+                    let cfs = names.iter().map(|name| name.as_str()).collect::<Vec<_>>();
+                    DB::open_cf(&opts, path, &cfs, &cf_opts)
                 }
             }
-            Err(e) => return Err(e.into()),
+        };
+
+        let db = match open(&names) {
+            Ok(db) => db,
+            Err(ref s)
+                if s.starts_with("Corruption:") || s.starts_with(
+                    "Invalid argument: You have to open all column families",
+                ) =>
+            {
+                warn!(target:"db","DB corrupted: {}, attempting repair", s);
+                DB::repair(&opts, path)?;
+                open(&names)?
+            }
+            Err(s) => return Err(s.into()),
+        };
+        Ok(Rockskvdb {
+            db: db,
+            write_options: write_opts,
+            read_options: read_opts,
+            block_cache_options: block_opts,
+            block_cache: block_cache,
+            write_mode: config.write_mode,
+            counters: Rockskvdb::counters(&names),
+            columns: names,
+            overlay: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Whether `col` names the default column or an opened column family.
+    pub fn has_column(&self, col: &str) -> bool { col == DEFAULT_COLUMN || self.columns.iter().any(|c| c == col) }
+
+    /// Create column family `col` tuned by `config`.
+    pub fn create_column(&mut self, col: &str, config: &DatabaseConfig) -> Result<(), String> {
+        if self.has_column(col) {
+            return Err(format!("column {} already exists", col));
         }
+        let opts = Rockskvdb::parse_options(config, &Rockskvdb::block_options(config, &self.block_cache))?;
+        self.db.create_cf(col, &opts)?;
+        self.columns.push(col.to_string());
+        self.counters.insert(col.to_string(), Counters::default());
+        Ok(())
+    }
+
+    /// Drop column family `col` with all of its data, including unflushed writes.
+    pub fn drop_column(&mut self, col: &str) -> Result<(), String> {
+        if col == DEFAULT_COLUMN || !self.has_column(col) {
+            return Err(format!("column {} can not be dropped", col));
+        }
+        self.overlay.get_mut().remove(col);
+        self.db.drop_cf(col)?;
+        self.columns.retain(|c| c != col);
+        self.counters.remove(col);
+        Ok(())
+    }
+
+    /// Statistics of column `col`, `None` if it does not exist.
+    pub fn stats_cf(&self, col: &str) -> Option<DbStats> {
+        let counters = self.counters.get(col)?;
+        let (overlay_keys, overlay_size) = match self.overlay.read().get(col) {
+            Some(changes) => {
+                let size = changes
+                    .iter()
+                    .map(|(key, keystate)| {
+                        key.len() + match *keystate {
+                            KeyState::Insert(ref value) => value.len(),
+                            KeyState::Delete => 0,
+                        }
+                    })
+                    .sum::<usize>();
+                (changes.len(), size)
+            }
+            None => (0, 0),
+        };
+        Some(DbStats {
+            estimated_keys: self.property(col, "rocksdb.estimate-num-keys"),
+            sst_size: self.property(col, "rocksdb.total-sst-files-size"),
+            memtable_size: self.property(col, "rocksdb.cur-size-all-mem-tables"),
+            pending_compaction_bytes: self.property(col, "rocksdb.estimate-pending-compaction-bytes"),
+            block_cache_usage: self.property(col, "rocksdb.block-cache-usage"),
+            overlay_keys: overlay_keys as u64,
+            overlay_size: overlay_size as u64,
+            reads: counters.reads.load(AtomicOrdering::Relaxed) as u64,
+            writes: counters.writes.load(AtomicOrdering::Relaxed) as u64,
+        })
     }
-    pub fn flush(&mut self) -> Result<(), String> {
+
+    /// Numeric rocksdb property `name` of column `col`, 0 if unavailable.
+    fn property(&self, col: &str, name: &str) -> u64 {
+        match col {
+            DEFAULT_COLUMN => self.db.get_int_property(name),
+            col => {
+                self.cf(col)
+                    .map(|cf| self.db.get_int_property_cf(cf, name))
+                    .unwrap_or(0)
+            }
+        }
+    }
+
+    fn counters(columns: &[String]) -> HashMap<String, Counters> {
+        columns
+            .iter()
+            .map(|col| col.as_str())
+            .chain(Some(DEFAULT_COLUMN))
+            .map(|col| (col.to_string(), Counters::default()))
+            .collect()
+    }
+
+    fn count<F>(&self, col: &str, counter: F, n: usize)
+    where F: Fn(&Counters) -> &AtomicUsize {
+        if let Some(counters) = self.counters.get(col) {
+            counter(counters).fetch_add(n, AtomicOrdering::Relaxed);
+        }
+    }
+
+    /// Write all unflushed changes of every column to disk in one batch.
+    pub fn flush(&self) -> Result<(), String> { self.commit(DBTransaction::with_capacity(0)) }
+
+    /// Compact the keys in `[from, to]` of column `col`, `None` meaning the first or the last key.
+    pub fn compact_range_cf(&self, col: &str, from: Option<&[u8]>, to: Option<&[u8]>) -> Result<(), String> {
+        match col {
+            DEFAULT_COLUMN => self.db.compact_range(from, to),
+            col => self.db.compact_range_cf(self.cf(col)?, from, to),
+        }
+        Ok(())
+    }
+
+    /// Flush and copy the database to the new directory `path`, hard linking the sst files
+    /// when it is on the same filesystem. The copy opens like the original.
+    pub fn checkpoint(&self, path: &str) -> Result<(), String> {
+        self.flush()?;
+        self.db.create_checkpoint(path)
+    }
+
+    /// Rebuild the metadata of the closed database at `path` from its files, dropping
+    /// whatever can not be recovered.
+    pub fn repair(config: &DatabaseConfig, path: &str) -> Result<(), String> {
+        let block_cache = Cache::new(cmp::max(8, config.memory_budget));
+        let opts = Rockskvdb::parse_options(config, &Rockskvdb::block_options(config, &block_cache))?;
+        DB::repair(&opts, path)
+    }
+
+    /// Copy every key of the default column of `from` into column `col`, in batches written
+    /// straight to disk. Returns the number of copied keys.
+    pub fn import_cf(&self, col: &str, from: &Rockskvdb) -> Result<usize, String> {
+        let mut count = 0;
+        let mut batch = WriteBatch::new();
+        for (key, value) in from.iter() {
+            batch.put_cf(self.cf(col)?, &key, &value)?;
+            count += 1;
+            if count % OVERLAY_FLUSH_THRESHOLD == 0 {
+                self.db.write_opt(batch, &self.write_options)?;
+                batch = WriteBatch::new();
+            }
+        }
+        self.db.write_opt(batch, &self.write_options)?;
+        self.count(col, |c| &c.writes, count);
+        Ok(count)
+    }
+
+    /// Commit `transaction`: straight to disk in `Durable` mode, to the overlay in `Buffered` mode.
+    pub fn write(&self, transaction: DBTransaction) -> Result<(), String> {
+        match self.write_mode {
+            WriteMode::Durable => self.commit(transaction),
+            WriteMode::Buffered => {
+                self.write_buffered(transaction);
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the unflushed changes together with `transaction` to disk in one batch.
+    fn commit(&self, transaction: DBTransaction) -> Result<(), String> {
+        let mut overlay = self.overlay.write();
         let batch = WriteBatch::new();
-        for (ref key, ref keystate) in self.overlay.drain() {
-            match (key, keystate) {
-                (key, KeyState::Delete) => {
-                    batch.delete(&key)?;
+        for (col, changes) in overlay.iter() {
+            for (key, keystate) in changes {
+                match *keystate {
+                    KeyState::Insert(ref value) => self.batch_op(&batch, col, key, Some(value))?,
+                    KeyState::Delete => self.batch_op(&batch, col, key, None)?,
                 }
-                (key, KeyState::Insert(ref value)) => {
-                    batch.put(&key, &value)?;
+            }
+        }
+        for (col, ops) in transaction.ops.iter_all() {
+            if !self.has_column(col) {
+                error!(target:"db","db:{} not found",col);
+                continue;
+            }
+            self.count(col, |c| &c.writes, ops.len());
+            for op in ops {
+                match *op {
+                    DBOp::Delete {
+                        ref key,
+                    } => {
+                        trace!(target:"db", "db:{}, Delete key = {:?}", col, key);
+                        self.batch_op(&batch, col, key, None)?;
+                    }
+                    DBOp::Insert {
+                        ref key,
+                        ref value,
+                    } => {
+                        trace!(target:"db", "db:{}, Put key = {:?}, value = {:?}", col, key, value);
+                        self.batch_op(&batch, col, key, Some(value))?;
+                    }
                 }
             }
         }
         self.db.write_opt(batch, &self.write_options)?;
-        self.overlay.clear();
+        overlay.clear();
         Ok(())
     }
+
+    fn batch_op(&self, batch: &WriteBatch, col: &str, key: &[u8], value: Option<&DBValue>) -> Result<(), String> {
+        match (col, value) {
+            (DEFAULT_COLUMN, None) => batch.delete(key),
+            (DEFAULT_COLUMN, Some(value)) => batch.put(key, value),
+            (col, None) => batch.delete_cf(self.cf(col)?, key),
+            (col, Some(value)) => batch.put_cf(self.cf(col)?, key, value),
+        }
+    }
+
+    /// Stage the operations of `transaction`, one column family per db name. Operations for
+    /// unknown columns are skipped. Flushing only happens between whole transactions, so a
+    /// transaction always reaches the disk atomically.
+    pub fn write_buffered(&self, transaction: DBTransaction) {
+        let len = {
+            let mut overlay = self.overlay.write();
+            for (col, ops) in transaction.ops.iter_all() {
+                if !self.has_column(col) {
+                    error!(target:"db","db:{} not found",col);
+                    continue;
+                }
+                self.count(col, |c| &c.writes, ops.len());
+                let changes = overlay.entry(col.to_string()).or_default();
+                for op in ops {
+                    match *op {
+                        DBOp::Delete {
+                            ref key,
+                        } => {
+                            trace!(target:"db", "db:{}, Delete key = {:?}", col, key);
+                            changes.insert(key.clone(), KeyState::Delete);
+                        }
+                        DBOp::Insert {
+                            ref key,
+                            ref value,
+                        } => {
+                            trace!(target:"db", "db:{}, Put key = {:?}, value = {:?}", col, key, value);
+                            changes.insert(key.clone(), KeyState::Insert(value.clone()));
+                        }
+                    }
+                }
+            }
+            overlay.values().map(|changes| changes.len()).sum::<usize>()
+        };
+        if len > OVERLAY_FLUSH_THRESHOLD {
+            let _ = self.flush();
+        }
+    }
+
+    /// Get value by key from column `col`.
+    pub fn get_cf(&self, col: &str, k: &[u8]) -> Option<DBValue> {
+        self.get_at(self.overlay.read().get(col), &self.read_options, col, k)
+    }
+
+    /// Iterate over column `col`, including unflushed writes.
+    pub fn iter_cf(&self, col: &str) -> KeyValueIter { self.scan(col, Scan::Prefix(&[])) }
+
+    /// Get the value of the first key starting with `prefix` in column `col`,
+    /// including unflushed writes.
+    pub fn get_by_prefix_cf(&self, col: &str, prefix: &[u8]) -> Option<Box<[u8]>> {
+        self.iter_from_prefix_cf(col, prefix)
+            .next()
+            .map(|(_, v)| v)
+    }
+
+    /// Iterate over the keys starting with `prefix` in column `col`, including unflushed writes.
+    pub fn iter_from_prefix_cf(&self, col: &str, prefix: &[u8]) -> KeyValueIter { self.scan(col, Scan::Prefix(prefix)) }
+
+    /// Iterate over the keys in `start..end` in column `col`, including unflushed writes.
+    pub fn iter_range_cf(&self, col: &str, start: &[u8], end: &[u8]) -> KeyValueIter {
+        self.scan(col, Scan::Range(start, end))
+    }
+
+    /// Iterate over column `col` from the last key down, including unflushed writes.
+    pub fn iter_rev_cf(&self, col: &str) -> KeyValueIter { self.scan(col, Scan::Reverse(None)) }
+
+    /// Iterate over the keys not greater than `key` in column `col` from `key` down,
+    /// including unflushed writes.
+    pub fn iter_rev_from_cf(&self, col: &str, key: &[u8]) -> KeyValueIter { self.scan(col, Scan::Reverse(Some(key))) }
+
+    /// Take a point-in-time view of every column, including unflushed writes.
+    pub fn snapshot(&self) -> RockskvdbSnapshot<'_> {
+        // commits hold the overlay lock, so the overlay matches the rocksdb snapshot.
+        let overlay = self.overlay.read();
+        let snapshot = self.db.snapshot();
+        let mut read_options = ReadOptions::new();
+        read_options.set_verify_checksums(false);
+        read_options.set_total_order_seek(true);
+        read_options.set_snapshot(&snapshot);
+        RockskvdbSnapshot {
+            db: self,
+            read_options: read_options,
+            overlay: overlay.clone(),
+            _snapshot: snapshot,
+        }
+    }
+
+    fn scan(&self, col: &str, scan: Scan) -> KeyValueIter {
+        self.scan_at(self.overlay.read().get(col), &self.read_options, col, scan)
+    }
+
+    /// Read `k` from column `col` as seen through `overlay` and `read_options`.
+    fn get_at(&self, overlay: Option<&Overlay>, read_options: &ReadOptions, col: &str, k: &[u8]) -> Option<DBValue> {
+        self.count(col, |c| &c.reads, 1);
+        match overlay.and_then(|changes| changes.get(k)) {
+            Some(KeyState::Insert(ref value)) => return Some(value.clone()),
+            Some(KeyState::Delete) => return None,
+            None => {}
+        }
+        let res = match col {
+            DEFAULT_COLUMN => self.db.get_opt(k, read_options),
+            col => {
+                self.cf(col)
+                    .and_then(|cf| self.db.get_cf_opt(cf, k, read_options))
+            }
+        };
+        res.unwrap_or(None).map(|r| DBValue::from_slice(&r))
+    }
+
+    /// Iterate over column `col` as seen through `overlay` and `read_options`.
+    fn scan_at(&self, overlay: Option<&Overlay>, read_options: &ReadOptions, col: &str, scan: Scan) -> KeyValueIter {
+        self.count(col, |c| &c.reads, 1);
+        let mut overlay_data = overlay
+            .map(|changes| {
+                changes
+                    .iter()
+                    .filter(|(k, _)| scan.contains(k))
+                    .map(|(k, v)| {
+                        let value = match *v {
+                            KeyState::Insert(ref value) => Some(value.to_vec().into_boxed_slice()),
+                            KeyState::Delete => None,
+                        };
+                        (k.to_vec().into_boxed_slice(), value)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        overlay_data.sort();
+
+        let db_iter: Option<KeyValueIter> = match scan {
+            Scan::Prefix(prefix) => {
+                let mode = match prefix.is_empty() {
+                    true => IteratorMode::Start,
+                    false => IteratorMode::From(prefix, Direction::Forward),
+                };
+                let prefix = prefix.to_vec();
+                self.db_iter(read_options, col, mode).map(|iter| {
+                    Box::new(iter.take_while(move |(k, _)| k.starts_with(&prefix))) as KeyValueIter
+                })
+            }
+            Scan::Range(start, end) => {
+                let end = end.to_vec();
+                self.db_iter(read_options, col, IteratorMode::From(start, Direction::Forward))
+                    .map(|iter| Box::new(iter.take_while(move |(k, _)| **k < *end)) as KeyValueIter)
+            }
+            Scan::Reverse(None) => self.db_iter(read_options, col, IteratorMode::End).map(|iter| Box::new(iter) as KeyValueIter),
+            Scan::Reverse(Some(last)) => {
+                // seeking lands on the first key not less than `last`, or nowhere if
+                // every key is less.
+                let iter = match self.db_iter(read_options, col, IteratorMode::From(last, Direction::Reverse)) {
+                    Some(ref iter) if !iter.valid() => self.db_iter(read_options, col, IteratorMode::End),
+                    iter => iter,
+                };
+                let last = last.to_vec();
+                iter.map(|iter| Box::new(iter.skip_while(move |(k, _)| **k > *last)) as KeyValueIter)
+            }
+        };
+        let reverse = match scan {
+            Scan::Reverse(_) => {
+                overlay_data.reverse();
+                true
+            }
+            _ => false,
+        };
+        Box::new(RockskvdbIterator {
+            overlay: overlay_data.into_iter().peekable(),
+            db: db_iter.unwrap_or_else(|| Box::new(None.into_iter())).peekable(),
+            reverse: reverse,
+        })
+    }
+
+    fn cf(&self, col: &str) -> Result<::parity_rocksdb::Column, String> {
+        self.db
+            .cf_handle(col)
+            .ok_or_else(|| format!("column {} not found", col))
+    }
+
+    fn db_iter(&self, read_options: &ReadOptions, col: &str, mode: IteratorMode) -> Option<DBIterator> {
+        match col {
+            DEFAULT_COLUMN => Some(self.db.iterator_opt(mode, read_options)),
+            col => {
+                self.cf(col)
+                    .and_then(|cf| self.db.iterator_cf_opt(cf, mode, read_options))
+                    .ok()
+            }
+        }
+    }
+
+    /// Block options of `config`, with a cache of its own if it has a cache share, `shared`
+    /// otherwise.
+    fn block_options(config: &DatabaseConfig, shared: &Cache) -> BlockBasedOptions {
+        let mut block_opts = BlockBasedOptions::new();
+        block_opts.set_block_size(config.block_size);
+        match config.cache_share {
+            Some(share) => {
+                let cache_size = (config.memory_budget as f64 * share) as usize;
+                block_opts.set_cache(Cache::new(cmp::max(8, cache_size)))
+            }
+            None => block_opts.set_shared_cache(shared),
+        }
+        if let Some(bits) = config.bloom_filter_bits {
+            block_opts.set_filter(bits);
+        }
+        block_opts
+    }
+
     fn parse_options(
         config: &DatabaseConfig,
         block_cache_config: &BlockBasedOptions,
@@ -145,7 +632,6 @@ impl Rockskvdb {
         opts.set_use_fsync(config.use_fsync);
         opts.set_compaction_style(DBCompactionStyle::DBLevelCompaction);
         opts.set_bytes_per_sync(config.bytes_per_sync);
-        opts.set_block_cache_size_mb(config.compact_options.block_size as u64);
         opts.set_target_file_size_base(config.compact_options.initial_file_size);
         opts.set_table_cache_num_shard_bits(config.table_cache_num_shard);
         opts.set_max_write_buffer_number(config.max_write_buffer_number);
@@ -171,25 +657,31 @@ impl Rockskvdb {
 
         opts.optimize_level_style_compaction(config.memory_budget as i32);
 
-        if config.disable_compress {
-            opts.set_parsed_options(&format!(
-                "compression_per_level={}:{}:{}:{}:{}:{}",
-                "kSnappyCompression",
-                "kSnappyCompression",
-                "kSnappyCompression",
-                "kSnappyCompression",
-                "kSnappyCompression",
-                "kSnappyCompression"
-            ))?;
-        } else {
+        if let Some(len) = config.prefix_len {
+            // iterators seek in total order, the prefix only feeds the bloom filters.
+            opts.set_prefix_extractor_fixed_size(len);
+            opts.set_parsed_options("memtable_prefix_bloom_size_ratio=0.1")?;
+        }
+
+        let compression = &config.compression;
+        // the level style optimization has filled compression_per_level, which takes
+        // precedence over compression, so no compression must be set per level too.
+        let per_level = match compression.per_level.is_empty() {
+            true => vec![CompressionType::None.rocksdb_name()],
+            false => {
+                compression
+                    .per_level
+                    .iter()
+                    .map(|algorithm| algorithm.rocksdb_name())
+                    .collect::<Vec<_>>()
+            }
+        };
+        opts.set_parsed_options(&format!("compression_per_level={}", per_level.join(":")))?;
+        if let Some(dictionary) = compression.zstd_dictionary {
+            // window bits, level and strategy stay at the rocksdb defaults.
             opts.set_parsed_options(&format!(
-                "compression_per_level={}:{}:{}:{}:{}:{}",
-                "kNoCompression",
-                "kNoCompression",
-                "kNoCompression",
-                "kNoCompression",
-                "kNoCompression",
-                "kNoCompression"
+                "compression_opts=-14:32767:0:{}:{}",
+                dictionary.max_dict_bytes, dictionary.max_train_bytes
             ))?;
         }
 
@@ -197,90 +689,85 @@ impl Rockskvdb {
     }
 }
 
-impl KeyValueDAO for Rockskvdb {
-    fn get(&self, k: &[u8]) -> Option<DBValue> {
-        match self.overlay.get(k) {
-            Some(KeyState::Insert(ref value)) => Some(value.clone()),
-            Some(KeyState::Delete) => None,
-            None => {
-                self.db
-                    .get_opt(k, &self.read_options)
-                    .unwrap_or(None)
-                    .map(|r| DBValue::from_slice(&r))
-            }
-        }
-    }
+/// Point-in-time view of a `Rockskvdb`, unaffected by later writes.
+pub struct RockskvdbSnapshot<'a> {
+    db: &'a Rockskvdb,
+    read_options: ReadOptions,
+    overlay: HashMap<String, Overlay>,
+    _snapshot: Snapshot<'a>,
+}
 
-    fn put(&mut self, k: &[u8], v: &DBValue) -> Option<DBValue> {
-        let mut ekey = Key::new();
-        ekey.append_slice(k);
-        self.overlay.insert(ekey, KeyState::Insert(v.clone()));
-        if self.overlay.len() > 10000 {
-            let _ = self.flush();
-        }
-        Some(v.clone())
+impl<'a> RockskvdbSnapshot<'a> {
+    /// Get value by key from column `col`.
+    pub fn get_cf(&self, col: &str, k: &[u8]) -> Option<DBValue> {
+        self.db
+            .get_at(self.overlay.get(col), &self.read_options, col, k)
     }
 
-    fn delete(&mut self, k: &[u8]) -> Option<DBValue> {
-        let mut ekey = Key::new();
-        ekey.append_slice(k);
-        self.overlay.insert(ekey, KeyState::Delete);
-        if self.overlay.len() > 10000 {
-            let _ = self.flush();
-        }
-        // ignore the result
-        Some(DBValue::from_slice(k))
-    }
+    /// Iterate over column `col`.
+    pub fn iter_cf(&self, col: &str) -> KeyValueIter { self.iter_from_prefix_cf(col, &[]) }
 
-    fn iter(&self) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
-        let mut overlay_data = self
-            .overlay
-            .iter()
-            .filter_map(|(k, v)| {
-                match *v {
-                    KeyState::Insert(ref value) => {
-                        Some((
-                            k.clone().to_vec().into_boxed_slice(),
-                            value.clone().to_vec().into_boxed_slice(),
-                        ))
-                    }
-                    KeyState::Delete => None,
-                }
-            })
-            .collect::<Vec<_>>();
-        overlay_data.sort();
-        let db_iter = self
-            .db
-            .iterator_opt(IteratorMode::Start, &self.read_options);
-        Box::new(RockskvdbIterator {
-            iter: interleave_ordered(overlay_data, db_iter),
-            _marker: PhantomData,
-        })
+    /// Iterate over the keys starting with `prefix` in column `col`.
+    pub fn iter_from_prefix_cf(&self, col: &str, prefix: &[u8]) -> KeyValueIter {
+        self.db.scan_at(
+            self.overlay.get(col),
+            &self.read_options,
+            col,
+            Scan::Prefix(prefix),
+        )
     }
 
-    fn get_by_prefix(&self, prefix: &[u8]) -> Option<Box<[u8]>> {
-        let mut iter = self.db.iterator_opt(
-            IteratorMode::From(prefix, Direction::Forward),
+    /// Iterate over the keys in `start..end` in column `col`.
+    pub fn iter_range_cf(&self, col: &str, start: &[u8], end: &[u8]) -> KeyValueIter {
+        self.db.scan_at(
+            self.overlay.get(col),
             &self.read_options,
-        );
-        match iter.next() {
-            Some((k, v)) => {
-                if k[0..prefix.len()] == prefix[..] {
-                    Some(v)
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        }
+            col,
+            Scan::Range(start, end),
+        )
     }
 
-    fn iter_from_prefix(&self, prefix: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>> {
-        Box::new(self.db.iterator_opt(
-            IteratorMode::From(prefix, Direction::Forward),
+    /// Iterate over the keys not greater than `key` in column `col` from `key` down.
+    pub fn iter_rev_from_cf(&self, col: &str, key: &[u8]) -> KeyValueIter {
+        self.db.scan_at(
+            self.overlay.get(col),
             &self.read_options,
-        ))
+            col,
+            Scan::Reverse(Some(key)),
+        )
+    }
+
+    /// Whether `col` names the default column or an opened column family.
+    pub fn has_column(&self, col: &str) -> bool { self.db.has_column(col) }
+}
+
+impl KeyValueDAO for Rockskvdb {
+    fn get(&self, k: &[u8]) -> Option<DBValue> { self.get_cf(DEFAULT_COLUMN, k) }
+
+    fn put(&mut self, k: &[u8], v: &DBValue) -> Option<DBValue> {
+        let mut transaction = DBTransaction::with_capacity(1);
+        transaction.put(DEFAULT_COLUMN, k, v);
+        self.write(transaction).ok().map(|_| v.clone())
+    }
+
+    fn delete(&mut self, k: &[u8]) -> Option<DBValue> {
+        let mut transaction = DBTransaction::with_capacity(1);
+        transaction.delete(DEFAULT_COLUMN, k);
+        // ignore the result
+        self.write(transaction).ok().map(|_| DBValue::from_slice(k))
     }
+
+    fn iter(&self) -> KeyValueIter { self.iter_cf(DEFAULT_COLUMN) }
+
+    fn get_by_prefix(&self, prefix: &[u8]) -> Option<Box<[u8]>> { self.get_by_prefix_cf(DEFAULT_COLUMN, prefix) }
+
+    fn iter_from_prefix(&self, prefix: &[u8]) -> KeyValueIter { self.iter_from_prefix_cf(DEFAULT_COLUMN, prefix) }
+
+    fn iter_range(&self, start: &[u8], end: &[u8]) -> KeyValueIter { self.iter_range_cf(DEFAULT_COLUMN, start, end) }
+
+    fn iter_rev(&self) -> KeyValueIter { self.iter_rev_cf(DEFAULT_COLUMN) }
+
+    fn iter_rev_from(&self, key: &[u8]) -> KeyValueIter { self.iter_rev_from_cf(DEFAULT_COLUMN, key) }
 }
 impl Drop for Rockskvdb {
     fn drop(&mut self) { let _ = self.flush(); }
@@ -289,6 +776,7 @@ impl Drop for Rockskvdb {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dbconfigs::{DbProfile, ZstdDictionary};
     use std::fs;
 
     #[test]
@@ -331,4 +819,84 @@ mod tests {
         );
         let _ = fs::remove_dir_all("./temp/testdb_open");
     }
+
+    #[test]
+    fn compression_test() {
+        let sst_size = |per_level: Vec<CompressionType>| {
+            let path = "./temp/testdb_compression";
+            // small levels, so the data is compacted down to level 2 and below.
+            let mut config = DatabaseConfig {
+                memory_budget: 64 * 1024,
+                ..DatabaseConfig::default()
+            };
+            config.compression.per_level = per_level;
+            config.compression.zstd_dictionary = Some(ZstdDictionary {
+                max_dict_bytes: 16 * 1024,
+                max_train_bytes: 0,
+            });
+            let size = {
+                let db = Rockskvdb::open(&config, path).unwrap();
+                for chunk in 0..40u32 {
+                    let mut batch = DBTransaction::new();
+                    for i in chunk * 100..(chunk + 1) * 100 {
+                        batch.put(DEFAULT_COLUMN, &i.to_be_bytes(), &[i as u8; 1000]);
+                    }
+                    db.write(batch).unwrap();
+                    db.flush().unwrap();
+                }
+                db.compact_range_cf(DEFAULT_COLUMN, None, None).unwrap();
+                let files_at = |level: usize| {
+                    db.db
+                        .get_property_value(&format!("rocksdb.num-files-at-level{}", level))
+                        .parse::<u64>()
+                        .unwrap()
+                };
+                assert_eq!(files_at(0) + files_at(1), 0);
+                db.stats_cf(DEFAULT_COLUMN).unwrap().sst_size
+            };
+            let _ = fs::remove_dir_all(path);
+            size
+        };
+        let plain = 4000 * 1000;
+        assert!(sst_size(vec![]) > plain);
+        assert!(sst_size(vec![CompressionType::None]) > plain);
+        assert!(sst_size(vec![CompressionType::Snappy]) < plain / 5);
+        // levels 0 and 1 uncompressed, Snappy on the deeper levels holding the data.
+        let snappy_below_l1 = vec![CompressionType::None, CompressionType::None, CompressionType::Snappy];
+        assert!(sst_size(snappy_below_l1) < plain / 5);
+    }
+
+    #[test]
+    fn block_cache_test() {
+        let path = "./temp/testdb_block_cache";
+        let config = DatabaseConfig::default();
+        let columns = vec![
+            ("state".to_string(), DbProfile::PointLookup.apply(&config)),
+            ("meta".to_string(), DbProfile::Metadata.apply(&config)),
+            ("plain".to_string(), DbProfile::General.apply(&config)),
+        ];
+        {
+            let db = Rockskvdb::open_with_columns(&config, path, &columns).unwrap();
+            let capacity = |col: &str| db.property(col, "rocksdb.block-cache-capacity");
+            assert_eq!(capacity("state"), config.memory_budget as u64);
+            assert_eq!(capacity("meta"), (config.memory_budget as f64 * 0.05) as u64);
+            assert_eq!(capacity("plain"), config.memory_budget as u64);
+
+            let mut batch = DBTransaction::new();
+            for i in 0..1000u32 {
+                batch.put("plain", &i.to_be_bytes(), &[1; 100]);
+            }
+            db.write(batch).unwrap();
+            db.compact_range_cf("plain", None, None).unwrap();
+            for i in 0..1000u32 {
+                assert!(db.get_cf("plain", &i.to_be_bytes()).is_some());
+            }
+            // columns without a profile share the cache of the db, the others have their own.
+            let usage = |col: &str| db.stats_cf(col).unwrap().block_cache_usage;
+            assert!(usage("plain") > 0);
+            assert_eq!(usage(DEFAULT_COLUMN), usage("plain"));
+            assert!(usage("state") < usage("plain"));
+        }
+        let _ = fs::remove_dir_all(path);
+    }
 }
@@ -20,7 +20,7 @@
  ******************************************************************************/
 
 #![allow(dead_code)]
-use super::super::{DBTransaction, DbRepository, KeyValueDB, RepositoryConfig, DatabaseConfig};
+use super::super::{DBTransaction, DbRepository, KeyValueDB, RepositoryConfig, DatabaseConfig, DbProfile};
 use rand;
 use std::fs;
 use std::path::Path;
@@ -306,6 +306,7 @@ fn new_bench(dbpath: String, keyCount: u32, valueSizeBytes: u32) -> BenchmarkTes
     let dbrepository_configs = vec![RepositoryConfig {
         db_name: DB_NAME.into(),
         db_config: DatabaseConfig::default(),
+        profile: DbProfile::General,
         db_path: dbpath.clone(),
     }];
     let db = DbRepository::init(dbrepository_configs).unwrap();
@@ -391,7 +392,7 @@ fn benchtest_overwriteRandom() {
         {
             let db = bench.db.clone();
             let mut db = db.write().unwrap();
-            db.open_all();
+            db.open_all().unwrap();
         }
         bench.start();
         bench.overwrite(Order::RANDOM, 1000_000, 100, 1);
@@ -0,0 +1,134 @@
+/*******************************************************************************
+ * Copyright (c) 2018-2019 Aion foundation.
+ *
+ *     This file is part of the aion network project.
+ *
+ *     The aion network project is free software: you can redistribute it
+ *     and/or modify it under the terms of the GNU General Public License
+ *     as published by the Free Software Foundation, either version 3 of
+ *     the License, or any later version.
+ *
+ *     The aion network project is distributed in the hope that it will
+ *     be useful, but WITHOUT ANY WARRANTY; without even the implied
+ *     warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.
+ *     See the GNU General Public License for more details.
+ *
+ *     You should have received a copy of the GNU General Public License
+ *     along with the aion network project source files.
+ *     If not, see <https://www.gnu.org/licenses/>.
+ *
+ ******************************************************************************/
+
+//! Behaviour every `KeyValueDAO` backend must share.
+
+use super::super::{DBValue, DatabaseConfig, MemoryDB, Mockkvdb, Rockskvdb, WriteMode};
+use traits::KeyValueDAO;
+use std::fs;
+
+fn put(db: &mut KeyValueDAO, key: &[u8], value: &[u8]) { db.put(key, &DBValue::from_slice(value)); }
+
+fn keys<I: Iterator<Item = (Box<[u8]>, Box<[u8]>)>>(iter: I) -> Vec<Vec<u8>> { iter.map(|(k, _)| k.into_vec()).collect() }
+
+/// Run the checks against `db`, calling `flush` between them so that
+/// backends with a write buffer are checked with flushed, unflushed and mixed data.
+fn conformance<D: KeyValueDAO>(db: &mut D, flush: &Fn(&mut D)) {
+    for key in &[&b"b1"[..], b"a2", b"a", b"ab", b"a1", b"c"] {
+        put(db, key, key);
+    }
+    for round in 0..2 {
+        assert_eq!(db.get(b"a1").unwrap(), DBValue::from_slice(b"a1"));
+        assert_eq!(db.get(b"a3"), None);
+        assert_eq!(
+            keys(db.iter()),
+            vec![b"a".to_vec(), b"a1".to_vec(), b"a2".to_vec(), b"ab".to_vec(), b"b1".to_vec(), b"c".to_vec()]
+        );
+        // a prefix stops at the last key starting with it.
+        assert_eq!(
+            keys(db.iter_from_prefix(b"a")),
+            vec![b"a".to_vec(), b"a1".to_vec(), b"a2".to_vec(), b"ab".to_vec()]
+        );
+        assert_eq!(keys(db.iter_from_prefix(b"b")), vec![b"b1".to_vec()]);
+        assert!(keys(db.iter_from_prefix(b"a3")).is_empty());
+        assert!(keys(db.iter_from_prefix(b"d")).is_empty());
+        assert_eq!(&*db.get_by_prefix(b"a").unwrap(), b"a");
+        assert_eq!(&*db.get_by_prefix(b"b").unwrap(), b"b1");
+        assert_eq!(db.get_by_prefix(b"bb"), None);
+        assert_eq!(db.get_by_prefix(b"d"), None);
+        if round == 0 {
+            flush(db);
+        }
+    }
+
+    // changes over flushed data.
+    db.delete(b"a");
+    db.delete(b"a2");
+    put(db, b"a1", b"new");
+    put(db, b"a0", b"a0");
+    for round in 0..2 {
+        assert_eq!(db.get(b"a"), None);
+        assert_eq!(db.get(b"a1").unwrap(), DBValue::from_slice(b"new"));
+        assert_eq!(
+            keys(db.iter_from_prefix(b"a")),
+            vec![b"a0".to_vec(), b"a1".to_vec(), b"ab".to_vec()]
+        );
+        assert_eq!(&*db.get_by_prefix(b"a").unwrap(), b"a0");
+        assert_eq!(&*db.get_by_prefix(b"a1").unwrap(), b"new");
+        assert_eq!(db.get_by_prefix(b"a2"), None);
+        assert_eq!(db.iter().count(), 5);
+        if round == 0 {
+            flush(db);
+        }
+    }
+
+    // ranges and reverse iteration.
+    db.delete(b"c");
+    put(db, b"b0", b"b0");
+    for round in 0..2 {
+        assert_eq!(
+            keys(db.iter_range(b"a1", b"b1")),
+            vec![b"a1".to_vec(), b"ab".to_vec(), b"b0".to_vec()]
+        );
+        assert_eq!(keys(db.iter_range(b"a", b"b")).len(), 3);
+        assert!(keys(db.iter_range(b"b", b"a")).is_empty());
+        assert_eq!(
+            keys(db.iter_rev()),
+            vec![b"b1".to_vec(), b"b0".to_vec(), b"ab".to_vec(), b"a1".to_vec(), b"a0".to_vec()]
+        );
+        assert_eq!(
+            keys(db.iter_rev_from(b"b")),
+            vec![b"ab".to_vec(), b"a1".to_vec(), b"a0".to_vec()]
+        );
+        assert_eq!(keys(db.iter_rev_from(b"b0")).len(), 4);
+        assert_eq!(keys(db.iter_rev_from(b"z")).len(), 5);
+        assert!(keys(db.iter_rev_from(b"0")).is_empty());
+        assert_eq!(&*db.last().unwrap().0, b"b1");
+        if round == 0 {
+            flush(db);
+        }
+    }
+}
+
+#[test]
+fn conformance_mockkvdb() { conformance(&mut Mockkvdb::new_default(), &|_| {}); }
+
+#[test]
+fn conformance_memorydb() { conformance(&mut MemoryDB::new(), &|_| {}); }
+
+fn conformance_rockskvdb(path: &str, write_mode: WriteMode) {
+    let _ = fs::remove_dir_all(path);
+    {
+        let config = DatabaseConfig {
+            write_mode: write_mode,
+            ..Default::default()
+        };
+        let mut db = Rockskvdb::open(&config, path).unwrap();
+        conformance(&mut db, &|db: &mut Rockskvdb| db.flush().unwrap());
+    }
+    let _ = fs::remove_dir_all(path);
+}
+
+#[test]
+fn conformance_rockskvdb_durable() { conformance_rockskvdb("./temp/conformance_durable", WriteMode::Durable); }
+
+#[test]
+fn conformance_rockskvdb_buffered() { conformance_rockskvdb("./temp/conformance_buffered", WriteMode::Buffered); }
@@ -20,3 +20,4 @@
  ******************************************************************************/
 
 mod DriverBenchmarkTest;
+mod KeyValueDAOTest;
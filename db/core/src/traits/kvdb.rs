@@ -21,6 +21,7 @@
 
 use super::{DBValue, Result};
 use dbtransaction::DBTransaction;
+use Key;
 /// basic kvdb operation.
 pub trait KeyValueDAO: Sync + Send {
     /// Get value by key
@@ -31,20 +32,39 @@ pub trait KeyValueDAO: Sync + Send {
     fn delete(&mut self, k: &[u8]) -> Option<DBValue>;
     /// Return an iterator
     fn iter(&self) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
-    /// Get value by partial key. Prefix size should match configured prefix size. Only searches flushed values.
+    /// Get the value of the first key starting with `prefix`, including unflushed writes.
     fn get_by_prefix(&self, prefix: &[u8]) -> Option<Box<[u8]>>;
-    /// Return an iterator, from the beginning the key that prefix size matching the configured prefix size
-    fn iter_from_prefix(
-        &self,
-        prefix: &'static [u8],
-    ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+    /// Return an iterator over the keys starting with `prefix` in order, including unflushed writes.
+    fn iter_from_prefix(&self, prefix: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+    /// Return an iterator over the keys in `start..end` in order.
+    fn iter_range(&self, start: &[u8], end: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+    /// Return an iterator from the last key down.
+    fn iter_rev(&self) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+    /// Return an iterator over the keys not greater than `key`, from `key` down.
+    fn iter_rev_from(&self, key: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+    /// Get the entry with the greatest key.
+    fn last(&self) -> Option<(Box<[u8]>, Box<[u8]>)> { self.iter_rev().next() }
+}
+/// Copy out the entries of an in-memory db, so the iterator does not borrow the db.
+pub fn entries<'a, I>(iter: I) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>
+where I: Iterator<Item = (&'a Key, &'a DBValue)> {
+    let entries = iter
+        .map(|(k, v)| {
+            (
+                k.to_vec().into_boxed_slice(),
+                v.to_vec().into_boxed_slice(),
+            )
+        })
+        .collect::<Vec<_>>();
+    Box::new(entries.into_iter())
 }
 /// db repository operation.
 pub trait KeyValueDB: Sync + Send {
     /// Get the value by key from the specified db
     fn get(&self, db_name: &str, key: &[u8]) -> Result<Option<DBValue>>;
     fn keys(&self) -> Option<Vec<String>>;
-    /// Commit transaction to database and flush to db
+    /// Commit transaction to database. Durable backends write it to disk before returning,
+    /// buffered ones may keep it in memory until `flush`.
     fn write(&self, transaction: DBTransaction) -> Result<()> {
         self.write_buffered(transaction);
         self.flush()
@@ -54,19 +74,54 @@ pub trait KeyValueDB: Sync + Send {
     /// Flush db
     fn flush(&self) -> Result<()> { Ok(()) }
     /// Return a specified db' iterator
-    fn iter(&self, db_name: &'static str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
-    /// Get value by partial key. Prefix size should match configured prefix size. Only searches flushed values.
-    fn get_by_prefix(&self, db_name: &'static str, prefix: &[u8]) -> Option<Box<[u8]>>;
-    /// Return an iterator, from the beginning the key that prefix size matching the configured prefix size
+    fn iter(&self, db_name: &str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+    /// Get the value of the first key starting with `prefix` in the specified db,
+    /// including unflushed writes.
+    fn get_by_prefix(&self, db_name: &str, prefix: &[u8]) -> Option<Box<[u8]>>;
+    /// Return an iterator over the keys starting with `prefix` in the specified db in order,
+    /// including unflushed writes.
     fn iter_from_prefix<'a>(
         &'a self,
-        db_name: &'static str,
-        prefix: &'static [u8],
+        db_name: &str,
+        prefix: &[u8],
+    ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+    /// Return an iterator over the keys in `start..end` in the specified db in order.
+    fn iter_range<'a>(
+        &'a self,
+        db_name: &str,
+        start: &[u8],
+        end: &[u8],
+    ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+    /// Return an iterator over the specified db from the last key down.
+    fn iter_rev<'a>(&'a self, db_name: &str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+    /// Return an iterator over the keys not greater than `key` in the specified db, from `key` down.
+    fn iter_rev_from<'a>(
+        &'a self,
+        db_name: &str,
+        key: &[u8],
     ) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)> + 'a>;
+    /// Get the entry with the greatest key in the specified db.
+    fn last(&self, db_name: &str) -> Option<(Box<[u8]>, Box<[u8]>)> { self.iter_rev(db_name).next() }
+    /// Take a point-in-time view of all dbs, including unflushed writes.
+    /// Later writes are not visible through the snapshot.
+    fn snapshot<'a>(&'a self) -> Box<KeyValueSnapshot + 'a>;
     /// Close all dbs
     #[cfg(test)]
     fn close_all(&mut self);
     /// Reopen all dbs
     #[cfg(test)]
-    fn open_all(&mut self);
+    fn open_all(&mut self) -> Result<()>;
+}
+/// read-only point-in-time view of a db repository.
+pub trait KeyValueSnapshot {
+    /// Get the value by key from the specified db
+    fn get(&self, db_name: &str, key: &[u8]) -> Result<Option<DBValue>>;
+    /// Return an iterator over the specified db in order
+    fn iter(&self, db_name: &str) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+    /// Return an iterator over the keys starting with `prefix` in the specified db in order.
+    fn iter_from_prefix(&self, db_name: &str, prefix: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+    /// Return an iterator over the keys in `start..end` in the specified db in order.
+    fn iter_range(&self, db_name: &str, start: &[u8], end: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
+    /// Return an iterator over the keys not greater than `key` in the specified db, from `key` down.
+    fn iter_rev_from(&self, db_name: &str, key: &[u8]) -> Box<Iterator<Item = (Box<[u8]>, Box<[u8]>)>>;
 }
@@ -23,5 +23,5 @@ mod kvdb;
 mod hashstore;
 
 use super::{DBValue, Result};
-pub use self::kvdb::{KeyValueDB, KeyValueDAO};
+pub use self::kvdb::{KeyValueDB, KeyValueDAO, KeyValueSnapshot, entries};
 pub use self::hashstore::{AsHashStore, HashStore};
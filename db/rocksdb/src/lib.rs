@@ -0,0 +1,27 @@
+// Copyright 2014 Tyler Neely
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//
+extern crate parity_rocksdb_sys as rocksdb_sys;
+extern crate local_encoding;
+
+pub use rocksdb_sys::rocksdb_ffi as rocksdb_ffi;
+pub use rocksdb_ffi::{DBCompactionStyle, DBComparator, new_bloom_filter};
+pub use rocksdb::{DB, DBIterator, DBVector, Direction, IteratorMode, Writable,
+                  WriteBatch, Column, ReadOptions};
+pub use rocksdb_options::{BlockBasedOptions, Options, WriteOptions, IndexType, Cache};
+pub use merge_operator::MergeOperands;
+pub mod rocksdb;
+pub mod rocksdb_options;
+pub mod merge_operator;
+pub mod comparator;